@@ -0,0 +1,454 @@
+use glam::Vec3;
+use wgpu::util::DeviceExt;
+
+use crate::renderer::{Sphere, MAX_SPHERES};
+
+// Grid cells are about this many mean sphere diameters wide
+const CELL_DIAMETERS: f32 = 2.0;
+
+// Keeps sparse scenes from allocating mostly empty grids
+const MAX_CELLS_PER_SPHERE: f32 = 4.0;
+
+/// Sphere-to-sphere ambient occlusion, computed from the exact sphere
+/// positions and radii rather than from the depth buffer. It is evaluated
+/// once per sphere, from its center, so a sphere much larger than its
+/// neighbours receives little of their occlusion.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmbientOcclusionSettings {
+    pub enabled: bool,
+    /// Occluders farther than this from a sphere's surface are ignored.
+    pub distance: f32,
+    /// Scales the occlusion before it darkens the ambient light.
+    pub strength: f32,
+}
+
+impl Default for AmbientOcclusionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            distance: 2.0,
+            strength: 1.0,
+        }
+    }
+}
+
+/// Mirrors `Params` in ambient_occlusion.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OcclusionUniform {
+    grid_origin: [f32; 3],
+    cell_size: f32,
+    grid_dims: [u32; 3],
+    sphere_count: u32,
+    distance: f32,
+    strength: f32,
+    _padding: [f32; 2],
+}
+
+/// Uniform grid over the sphere bounds. Every sphere is listed in each cell
+/// its bounding box touches, so large spheres are found from anywhere they
+/// reach.
+struct SphereGrid {
+    origin: Vec3,
+    cell_size: f32,
+    dims: [u32; 3],
+    /// Start of each cell's run in `entries`, plus a final end offset.
+    cell_offsets: Vec<u32>,
+    entries: Vec<u32>,
+}
+
+impl SphereGrid {
+    fn build(spheres: &[Sphere]) -> Self {
+        if spheres.is_empty() {
+            return Self {
+                origin: Vec3::ZERO,
+                cell_size: 1.0,
+                dims: [1; 3],
+                cell_offsets: vec![0, 0],
+                entries: Vec::new(),
+            };
+        }
+
+        let bounds = |sphere: &Sphere| {
+            let center = Vec3::from_array(sphere.position);
+            (center - sphere.radius, center + sphere.radius)
+        };
+        let (min, max) = spheres.iter().map(bounds).fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), (sphere_min, sphere_max)| (min.min(sphere_min), max.max(sphere_max)),
+        );
+        let extent = (max - min).max(Vec3::splat(1e-3));
+
+        let mean_radius = spheres.iter().map(|sphere| sphere.radius as f64).sum::<f64>() / spheres.len() as f64;
+        let max_cells = MAX_CELLS_PER_SPHERE * spheres.len() as f32;
+        let cell_size = (CELL_DIAMETERS * 2.0 * mean_radius as f32)
+            .max((extent.x * extent.y * extent.z / max_cells).cbrt())
+            .max(1e-3);
+        let dims = (extent / cell_size).ceil().max(Vec3::ONE).as_uvec3().to_array();
+
+        let cell_range = |sphere: &Sphere| {
+            let (sphere_min, sphere_max) = bounds(sphere);
+            let cell = |point: Vec3| {
+                let cell = ((point - min) / cell_size).floor().as_uvec3();
+                cell.min(glam::UVec3::from_array(dims) - 1)
+            };
+            (cell(sphere_min), cell(sphere_max))
+        };
+        let cell_index = |x: u32, y: u32, z: u32| ((z * dims[1] + y) * dims[0] + x) as usize;
+        let for_each_cell = |sphere: &Sphere, f: &mut dyn FnMut(usize)| {
+            let (first, last) = cell_range(sphere);
+            for z in first.z..=last.z {
+                for y in first.y..=last.y {
+                    for x in first.x..=last.x {
+                        f(cell_index(x, y, z));
+                    }
+                }
+            }
+        };
+
+        // Counting sort: sizes, then offsets, then fill
+        let cell_count = (dims[0] * dims[1] * dims[2]) as usize;
+        let mut cell_offsets = vec![0u32; cell_count + 1];
+        for sphere in spheres {
+            for_each_cell(sphere, &mut |cell| cell_offsets[cell + 1] += 1);
+        }
+        for cell in 0..cell_count {
+            cell_offsets[cell + 1] += cell_offsets[cell];
+        }
+        let mut cursors = cell_offsets[..cell_count].to_vec();
+        let mut entries = vec![0u32; cell_offsets[cell_count] as usize];
+        for (index, sphere) in spheres.iter().enumerate() {
+            for_each_cell(sphere, &mut |cell| {
+                entries[cursors[cell] as usize] = index as u32;
+                cursors[cell] += 1;
+            });
+        }
+
+        Self {
+            origin: min,
+            cell_size,
+            dims,
+            cell_offsets,
+            entries,
+        }
+    }
+}
+
+/// Per-sphere occlusion from neighbouring spheres, found through a
+/// [`SphereGrid`] and summed by a compute pass whenever the spheres or the
+/// settings change. The result stays on the GPU in
+/// [`Self::occlusion_buffer`], one `vec4` per sphere.
+pub(crate) struct SphereOcclusion {
+    settings: AmbientOcclusionSettings,
+    grid_origin: Vec3,
+    cell_size: f32,
+    grid_dims: [u32; 3],
+    sphere_count: u32,
+    uniform_buffer: wgpu::Buffer,
+    occlusion_buffer: wgpu::Buffer,
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    dirty: bool,
+}
+
+impl SphereOcclusion {
+    pub fn new(device: &wgpu::Device, sphere_buffer: &wgpu::Buffer) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Ambient Occlusion Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/ambient_occlusion.wgsl").into()),
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Ambient Occlusion Params Buffer"),
+            size: std::mem::size_of::<OcclusionUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let occlusion_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sphere Occlusion Buffer"),
+            size: (MAX_SPHERES * std::mem::size_of::<[f32; 4]>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Ambient Occlusion Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // spheres
+                storage_entry(1, true),
+                // cell_offsets
+                storage_entry(2, true),
+                // cell_entries
+                storage_entry(3, true),
+                // occlusion
+                storage_entry(4, false),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ambient Occlusion Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Ambient Occlusion Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let grid = SphereGrid::build(&[]);
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &uniform_buffer,
+            sphere_buffer,
+            &occlusion_buffer,
+            &grid,
+        );
+
+        Self {
+            settings: AmbientOcclusionSettings::default(),
+            grid_origin: grid.origin,
+            cell_size: grid.cell_size,
+            grid_dims: grid.dims,
+            sphere_count: 0,
+            uniform_buffer,
+            occlusion_buffer,
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            dirty: true,
+        }
+    }
+
+    pub fn occlusion_buffer(&self) -> &wgpu::Buffer {
+        &self.occlusion_buffer
+    }
+
+    pub fn settings(&self) -> AmbientOcclusionSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: AmbientOcclusionSettings) {
+        self.settings = settings;
+        self.dirty = true;
+    }
+
+    /// Rebuilds the grid for a new sphere list, which must be what
+    /// `sphere_buffer` holds.
+    pub fn set_spheres(&mut self, device: &wgpu::Device, sphere_buffer: &wgpu::Buffer, spheres: &[Sphere]) {
+        let grid = SphereGrid::build(spheres);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            sphere_buffer,
+            &self.occlusion_buffer,
+            &grid,
+        );
+        self.grid_origin = grid.origin;
+        self.cell_size = grid.cell_size;
+        self.grid_dims = grid.dims;
+        self.sphere_count = spheres.len() as u32;
+        self.dirty = true;
+    }
+
+    /// Recomputes the occlusion if anything changed since the last call.
+    pub fn encode(&mut self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        if !self.settings.enabled || self.sphere_count == 0 {
+            // Zero occlusion everywhere
+            encoder.clear_buffer(&self.occlusion_buffer, 0, None);
+            return;
+        }
+
+        let uniform = OcclusionUniform {
+            grid_origin: self.grid_origin.to_array(),
+            cell_size: self.cell_size,
+            grid_dims: self.grid_dims,
+            sphere_count: self.sphere_count,
+            distance: self.settings.distance.max(0.0),
+            strength: self.settings.strength,
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Ambient Occlusion Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.sphere_count.div_ceil(256), 1, 1);
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        sphere_buffer: &wgpu::Buffer,
+        occlusion_buffer: &wgpu::Buffer,
+        grid: &SphereGrid,
+    ) -> wgpu::BindGroup {
+        let cell_offsets = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Occlusion Grid Offsets Buffer"),
+            contents: bytemuck::cast_slice(&grid.cell_offsets),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        // Storage bindings can't be empty
+        let entries: &[u32] = if grid.entries.is_empty() { &[0] } else { &grid.entries };
+        let cell_entries = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Occlusion Grid Entries Buffer"),
+            contents: bytemuck::cast_slice(entries),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Ambient Occlusion Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: sphere_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: cell_offsets.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: cell_entries.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: occlusion_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(position: [f32; 3], radius: f32) -> Sphere {
+        Sphere {
+            position,
+            radius,
+            material_index: 0,
+            _padding: [0; 3],
+        }
+    }
+
+    fn cell_entries(grid: &SphereGrid, cell: usize) -> &[u32] {
+        &grid.entries[grid.cell_offsets[cell] as usize..grid.cell_offsets[cell + 1] as usize]
+    }
+
+    // Cells of the grid a sphere's bounding box overlaps, clamped like build
+    fn touched_cells(grid: &SphereGrid, sphere: &Sphere) -> Vec<usize> {
+        let center = Vec3::from_array(sphere.position);
+        let dims = glam::UVec3::from_array(grid.dims);
+        let cell = |point: Vec3| {
+            let cell = ((point - grid.origin) / grid.cell_size).floor().max(Vec3::ZERO);
+            cell.as_uvec3().min(dims - 1)
+        };
+        let (first, last) = (cell(center - sphere.radius), cell(center + sphere.radius));
+        let mut cells = Vec::new();
+        for z in first.z..=last.z {
+            for y in first.y..=last.y {
+                for x in first.x..=last.x {
+                    cells.push(((z * dims.y + y) * dims.x + x) as usize);
+                }
+            }
+        }
+        cells
+    }
+
+    fn scattered_spheres() -> Vec<Sphere> {
+        (0..200)
+            .map(|i| {
+                let position = [(i % 7) as f32 * 1.7, (i % 11) as f32 * 0.9, (i / 13) as f32 * 1.3];
+                sphere(position, 0.2 + (i % 5) as f32 * 0.15)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_input() {
+        let grid = SphereGrid::build(&[]);
+        assert_eq!(grid.dims, [1; 3]);
+        assert_eq!(grid.cell_offsets, vec![0, 0]);
+        assert!(grid.entries.is_empty());
+    }
+
+    #[test]
+    fn huge_sphere_is_in_every_cell() {
+        let mut spheres = scattered_spheres();
+        spheres.push(sphere([5.0, 4.0, 10.0], 100.0));
+        let huge = (spheres.len() - 1) as u32;
+        let grid = SphereGrid::build(&spheres);
+
+        let cell_count = (grid.dims[0] * grid.dims[1] * grid.dims[2]) as usize;
+        assert!(cell_count > 1);
+        for cell in 0..cell_count {
+            assert!(cell_entries(&grid, cell).contains(&huge), "cell {cell} misses the huge sphere");
+        }
+    }
+
+    #[test]
+    fn offsets_match_entries() {
+        let spheres = scattered_spheres();
+        let grid = SphereGrid::build(&spheres);
+
+        let cell_count = (grid.dims[0] * grid.dims[1] * grid.dims[2]) as usize;
+        assert_eq!(grid.cell_offsets.len(), cell_count + 1);
+        assert_eq!(grid.cell_offsets[0], 0);
+        assert_eq!(*grid.cell_offsets.last().unwrap() as usize, grid.entries.len());
+        assert!(grid.cell_offsets.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let mut expected_entries = 0;
+        for (index, sphere) in spheres.iter().enumerate() {
+            let cells = touched_cells(&grid, sphere);
+            expected_entries += cells.len();
+            for cell in cells {
+                assert!(cell_entries(&grid, cell).contains(&(index as u32)), "sphere {index} missing from cell {cell}");
+            }
+        }
+        assert_eq!(grid.entries.len(), expected_entries);
+    }
+}
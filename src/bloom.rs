@@ -0,0 +1,374 @@
+use crate::post::{FullscreenPipelines, PostContext, PostEffect, PostTarget};
+use crate::tonemap::HDR_FORMAT;
+
+/// Most half resolution levels in the chain; each one doubles the reach.
+const MAX_LEVELS: u32 = 6;
+
+/// Glow around bright and emissive surfaces, blended over the HDR scene
+/// before tone mapping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    /// Fraction of the scene replaced by its blurred copy. Energy
+    /// conserving, so `0.0` is no bloom and `1.0` only the blur.
+    pub intensity: f32,
+    /// Spread of the upsampling filter in texels of each level; larger
+    /// values give a wider, softer glow.
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            intensity: 0.04,
+            radius: 1.0,
+        }
+    }
+}
+
+/// Mirrors `Params` in bloom.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    radius: f32,
+    level_scale: f32,
+    intensity: f32,
+    encode_srgb: u32,
+}
+
+/// Downsample chain and the pipelines that filter it. Each level is its own
+/// texture rather than a mip, for the same reason as the depth pyramid.
+pub(crate) struct Bloom {
+    settings: BloomSettings,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// The unfiltered scene the composite pass mixes the bloom into.
+    scene_layout: wgpu::BindGroupLayout,
+    downsample_first_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    composite_pipelines: FullscreenPipelines,
+    levels: Vec<BloomLevel>,
+}
+
+struct BloomLevel {
+    view: wgpu::TextureView,
+    /// Reads this level, for the next downsample or upsample.
+    bind_group: wgpu::BindGroup,
+}
+
+impl Bloom {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bloom Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(include_str!("shaders/srgb.wgsl"), "\n", include_str!("shaders/bloom.wgsl")).into(),
+            ),
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bloom Params Buffer"),
+            size: std::mem::size_of::<BloomUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bloom Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bloom Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let scene_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bloom Scene Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |label: &str, entry_point: &str, blend: Option<wgpu::BlendState>| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_fullscreen"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: HDR_FORMAT,
+                        blend,
+                        write_mask: wgpu::ColorWrites::COLOR,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        let downsample_first_pipeline = create_pipeline("Bloom First Downsample Pipeline", "fs_downsample_first", None);
+        let downsample_pipeline = create_pipeline("Bloom Downsample Pipeline", "fs_downsample", None);
+        let upsample_pipeline = create_pipeline(
+            "Bloom Upsample Pipeline",
+            "fs_upsample",
+            Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::REPLACE,
+            }),
+        );
+        // Writes whichever target comes next in the post chain, so it mixes
+        // the scene in itself instead of blending over it
+        let composite_pipelines = FullscreenPipelines::new(
+            device,
+            "Bloom Composite Pipeline",
+            shader,
+            &[&bind_group_layout, &scene_layout],
+            "fs_composite",
+        );
+
+        let levels = create_levels(device, &bind_group_layout, &uniform_buffer, &sampler, width, height);
+
+        Self {
+            settings: BloomSettings::default(),
+            uniform_buffer,
+            sampler,
+            bind_group_layout,
+            scene_layout,
+            downsample_first_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipelines,
+            levels,
+        }
+    }
+
+    pub fn settings(&self) -> BloomSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: BloomSettings) {
+        self.settings = settings;
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.levels =
+            create_levels(device, &self.bind_group_layout, &self.uniform_buffer, &self.sampler, width, height);
+    }
+}
+
+impl PostEffect for Bloom {
+    fn label(&self) -> &str {
+        "Bloom Pass"
+    }
+
+    fn enabled(&self) -> bool {
+        self.settings.enabled && self.settings.intensity > 0.0 && !self.levels.is_empty()
+    }
+
+    /// Blurs `input` down and back up the chain and writes it with the
+    /// result mixed in.
+    fn encode(&mut self, context: &mut PostContext, input: &wgpu::TextureView, output: &PostTarget) {
+        let uniform = BloomUniform {
+            radius: self.settings.radius,
+            level_scale: 1.0 / self.levels.len() as f32,
+            intensity: self.settings.intensity.min(1.0),
+            encode_srgb: output.encode_srgb as u32,
+        };
+        context.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let input_bind_group = create_bind_group(
+            context.device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            &self.sampler,
+            input,
+        );
+        let scene_bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bloom Scene Bind Group"),
+            layout: &self.scene_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(input),
+            }],
+        });
+
+        let encoder = &mut *context.encoder;
+        for (index, level) in self.levels.iter().enumerate() {
+            let (pipeline, source) = match index {
+                0 => (&self.downsample_first_pipeline, &input_bind_group),
+                _ => (&self.downsample_pipeline, &self.levels[index - 1].bind_group),
+            };
+            let load = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+            let mut pass = begin_pass(encoder, "Bloom Downsample Pass", &level.view, load);
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, source, &[]);
+            pass.draw(0..3, 0..1);
+        }
+
+        // Each level adds the tent-filtered level below it, so the first
+        // ends up holding the sum of all of them
+        for index in (1..self.levels.len()).rev() {
+            let target = &self.levels[index - 1].view;
+            let mut pass = begin_pass(encoder, "Bloom Upsample Pass", target, wgpu::LoadOp::Load);
+            pass.set_pipeline(&self.upsample_pipeline);
+            pass.set_bind_group(0, &self.levels[index].bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+
+        let pipeline = self.composite_pipelines.get(context.device, output.format);
+        let load = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        let mut pass = begin_pass(encoder, "Bloom Composite Pass", output.view, load);
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &self.levels[0].bind_group, &[]);
+        pass.set_bind_group(1, &scene_bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn begin_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    label: &str,
+    view: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    sampler: &wgpu::Sampler,
+    source: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Bloom Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(source),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+    })
+}
+
+/// Halves the size per level, stopping before either side drops below two
+/// texels or after [`MAX_LEVELS`].
+fn create_levels(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    sampler: &wgpu::Sampler,
+    width: u32,
+    height: u32,
+) -> Vec<BloomLevel> {
+    let mut levels = Vec::new();
+    let (mut level_width, mut level_height) = (width / 2, height / 2);
+    while levels.len() < MAX_LEVELS as usize && level_width >= 2 && level_height >= 2 {
+        let view = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Bloom Level Texture"),
+                size: wgpu::Extent3d {
+                    width: level_width,
+                    height: level_height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = create_bind_group(device, layout, uniform_buffer, sampler, &view);
+        levels.push(BloomLevel { view, bind_group });
+        level_width /= 2;
+        level_height /= 2;
+    }
+    levels
+}
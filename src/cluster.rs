@@ -0,0 +1,194 @@
+use glam::Mat4;
+
+// Screen tiles across, tiles down and depth slices. Must match cluster.wgsl
+// and fragment.wgsl.
+const CLUSTER_DIMS: [u32; 3] = [16, 9, 24];
+const CLUSTER_COUNT: u32 = CLUSTER_DIMS[0] * CLUSTER_DIMS[1] * CLUSTER_DIMS[2];
+
+// Words per cluster: a light count, then the light indices
+const CLUSTER_STRIDE: u32 = 128;
+
+// Slices end this many near plane distances out when the far plane is infinite
+const INFINITE_DEPTH_RATIO: f32 = 1.0e4;
+
+/// Most lights a single cluster lists. Lights are assigned in the order
+/// they were added, so past this the last ones added go dark in crowded
+/// parts of the view.
+pub const MAX_CLUSTER_LIGHTS: u32 = CLUSTER_STRIDE - 1;
+
+/// Mirrors `Params` in cluster.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClusterUniform {
+    view: [[f32; 4]; 4],
+    inverse_projection: [[f32; 4]; 4],
+    clip_planes: [f32; 4],
+}
+
+/// Clustered light assignment. The view frustum is divided into screen
+/// tiles and exponentially spaced depth slices, and a compute pass lists
+/// per cluster the lights whose range reaches it, so shading a fragment
+/// only visits the lights of its own cluster. The lists live on the GPU in
+/// [`Self::cluster_buffer`] and are rebuilt every frame.
+pub(crate) struct LightClusters {
+    uniform_buffer: wgpu::Buffer,
+    cluster_buffer: wgpu::Buffer,
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl LightClusters {
+    pub fn new(device: &wgpu::Device, light_buffer: &wgpu::Buffer) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light Cluster Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/cluster.wgsl").into()),
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Cluster Params Buffer"),
+            size: std::mem::size_of::<ClusterUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let cluster_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Cluster Buffer"),
+            size: (CLUSTER_COUNT * CLUSTER_STRIDE) as u64 * std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Light Cluster Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // lights
+                storage_entry(1, true),
+                // clusters
+                storage_entry(2, false),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Cluster Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light Cluster Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &uniform_buffer, light_buffer, &cluster_buffer);
+
+        Self {
+            uniform_buffer,
+            cluster_buffer,
+            pipeline,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    pub fn cluster_buffer(&self) -> &wgpu::Buffer {
+        &self.cluster_buffer
+    }
+
+    /// Rebinds the light list after it was reallocated.
+    pub fn set_light_buffer(&mut self, device: &wgpu::Device, light_buffer: &wgpu::Buffer) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            light_buffer,
+            &self.cluster_buffer,
+        );
+    }
+
+    /// Depth range the slices span for a camera with these clip planes.
+    /// Fragments look up their slice with the same range.
+    pub fn depth_range((near, far): (f32, f32)) -> (f32, f32) {
+        let far = if far.is_finite() { far } else { near * INFINITE_DEPTH_RATIO };
+        (near, far)
+    }
+
+    /// Assigns the lights to the clusters of a camera. `projection` must be
+    /// the one the sphere passes rasterize with, jitter included, and
+    /// `clip_planes` its near and far planes.
+    pub fn encode(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        (view, projection): (Mat4, Mat4),
+        clip_planes: (f32, f32),
+    ) {
+        let (near, far) = Self::depth_range(clip_planes);
+        let uniform = ClusterUniform {
+            view: view.to_cols_array_2d(),
+            inverse_projection: projection.inverse().to_cols_array_2d(),
+            clip_planes: [near, far, 0.0, 0.0],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Light Cluster Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(CLUSTER_COUNT.div_ceil(64), 1, 1);
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        light_buffer: &wgpu::Buffer,
+        cluster_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light Cluster Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: cluster_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+}
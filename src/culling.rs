@@ -0,0 +1,189 @@
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::mesh::SphereMesh;
+
+/// One LOD bucket per mesh level, plus the ray-cast billboard bucket used for
+/// sub-pixel spheres (and for everything in `RenderMode::Impostor`).
+pub const LOD_BUCKET_COUNT: usize = SphereMesh::LEVEL_COUNT + 1;
+pub const IMPOSTOR_BUCKET: usize = SphereMesh::LEVEL_COUNT;
+
+/// The six clip planes of a camera in world space, normals pointing inward.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a combined view-projection matrix, assuming
+    /// wgpu's 0..1 clip-space depth range.
+    pub fn from_view_projection(view_proj: Mat4) -> Self {
+        let r0 = view_proj.row(0);
+        let r1 = view_proj.row(1);
+        let r2 = view_proj.row(2);
+        let r3 = view_proj.row(3);
+
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2]
+            .map(|plane| plane / plane.truncate().length());
+
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+    }
+}
+
+/// Minimum projected sphere radius, in pixels, at which each mesh level is
+/// used. The culling pass picks the last level whose entry a sphere reaches,
+/// so they must be ascending; spheres smaller than the first entry are drawn
+/// as billboards.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LodThresholds {
+    pub min_radius_px: [f32; SphereMesh::LEVEL_COUNT],
+}
+
+impl Default for LodThresholds {
+    fn default() -> Self {
+        Self {
+            min_radius_px: [0.5, 3.0, 10.0, 30.0],
+        }
+    }
+}
+
+impl LodThresholds {
+    /// The same thresholds in ascending order.
+    pub fn ascending(mut self) -> Self {
+        self.min_radius_px.sort_by(f32::total_cmp);
+        self
+    }
+}
+
+/// Per-stage results of the most recently read back culling pass. Counters
+/// arrive a frame or two after the frame they describe.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub total: u32,
+    pub frustum_culled: u32,
+    /// Still hidden after the late pass retested them.
+    pub occlusion_culled: u32,
+    /// Drawn by the early pass.
+    pub early_drawn: u32,
+    /// Rejected by last frame's depth pyramid but visible against this frame's.
+    pub late_drawn: u32,
+    /// Translucent spheres drawn by the transparent pass, from either
+    /// culling pass and counted in neither of the above.
+    pub transparent_drawn: u32,
+    /// Drawn spheres per LOD bucket, translucent ones included: mesh levels
+    /// from coarsest to finest, then billboards.
+    pub lod_drawn: [u32; LOD_BUCKET_COUNT],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct CullUniform {
+    pub view_proj: [[f32; 4]; 4],
+    pub planes: [[f32; 4]; 6],
+    pub lod_thresholds: [f32; 4],
+    pub sphere_count: u32,
+    pub occlusion_enabled: u32,
+    pub hiz_levels: u32,
+    pub lod_scale: f32,
+    pub hiz_size: [u32; 2],
+    pub impostors_only: u32,
+    pub bucket_capacity: u32,
+}
+
+/// Mirrors `CullState` in compute.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct CullState {
+    pub late_dispatch: [u32; 3],
+    pub recheck_count: u32,
+    pub frustum_culled: u32,
+    pub occlusion_culled: u32,
+}
+
+impl CullState {
+    pub const RESET: Self = Self {
+        late_dispatch: [0, 1, 1],
+        recheck_count: 0,
+        frustum_culled: 0,
+        occlusion_culled: 0,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Camera at (0, 0, 5) looking down -Z with a 90 degree field of view, so
+    // the side planes are at 45 degrees, near at z = 4 and far at z = -95
+    fn frustum() -> Frustum {
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 0.0, 5.0), Vec3::ZERO, Vec3::Y);
+        let projection = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 100.0);
+        Frustum::from_view_projection(projection * view)
+    }
+
+    // A point on each plane with its outward unit normal, in plane order:
+    // left, right, bottom, top, near, far
+    fn plane_points() -> [(Vec3, Vec3); 6] {
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        [
+            (Vec3::new(-10.0, 0.0, -5.0), Vec3::new(-diagonal, 0.0, diagonal)),
+            (Vec3::new(10.0, 0.0, -5.0), Vec3::new(diagonal, 0.0, diagonal)),
+            (Vec3::new(0.0, -10.0, -5.0), Vec3::new(0.0, -diagonal, diagonal)),
+            (Vec3::new(0.0, 10.0, -5.0), Vec3::new(0.0, diagonal, diagonal)),
+            (Vec3::new(0.0, 0.0, 4.0), Vec3::Z),
+            (Vec3::new(0.0, 0.0, -95.0), Vec3::NEG_Z),
+        ]
+    }
+
+    #[test]
+    fn planes_are_normalized_and_face_inward() {
+        let frustum = frustum();
+        for (plane, (point, outward)) in frustum.planes.iter().zip(plane_points()) {
+            assert!((plane.truncate().length() - 1.0).abs() < 1e-4);
+            assert!(plane.truncate().dot(-outward) > 0.999, "{plane} should face {}", -outward);
+            let distance = plane.truncate().dot(point) + plane.w;
+            assert!(distance.abs() < 1e-3 * point.length().max(1.0), "{point} is {distance} from {plane}");
+        }
+    }
+
+    #[test]
+    fn sphere_inside_every_plane() {
+        let frustum = frustum();
+        assert!(frustum.intersects_sphere(Vec3::new(0.0, 0.0, -20.0), 1.0));
+        for (point, outward) in plane_points() {
+            assert!(frustum.intersects_sphere(point - outward * 2.0, 1.0), "inside near {point}");
+        }
+    }
+
+    #[test]
+    fn sphere_outside_each_plane() {
+        let frustum = frustum();
+        for (point, outward) in plane_points() {
+            assert!(!frustum.intersects_sphere(point + outward * 2.0, 1.0), "outside {point}");
+            assert!(!frustum.intersects_sphere(point + outward * 1.01, 1.0), "just outside {point}");
+        }
+    }
+
+    #[test]
+    fn sphere_straddling_each_plane() {
+        let frustum = frustum();
+        for (point, outward) in plane_points() {
+            assert!(frustum.intersects_sphere(point, 1.0), "centered on {point}");
+            assert!(frustum.intersects_sphere(point + outward * 0.99, 1.0), "straddling {point}");
+        }
+    }
+
+    #[test]
+    fn lod_thresholds_ascending() {
+        let thresholds = LodThresholds {
+            min_radius_px: [10.0, 0.5, 30.0, 3.0],
+        };
+        assert_eq!(thresholds.ascending(), LodThresholds::default());
+        assert_eq!(LodThresholds::default().ascending(), LodThresholds::default());
+    }
+}
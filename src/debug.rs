@@ -0,0 +1,483 @@
+use wgpu::util::DeviceExt;
+
+use crate::mesh::Vertex;
+use crate::post::{begin_fullscreen_pass, FullscreenPipelines, PostTarget};
+use crate::tonemap::HDR_FORMAT;
+
+// Values the resolve shows in full red. The light count view draws one
+// more than the count, so spheres in clusters without lights still show.
+const OVERDRAW_HEAT_MAX: f32 = 8.0;
+const LIGHT_COUNT_HEAT_MAX: f32 = 33.0;
+
+/// What a frame shows in place of the shaded scene, for telling apart
+/// geometry, normal, material and depth problems. Every view but
+/// [`DebugView::None`] skips lighting and post-processing and writes its
+/// colors straight to the target. Culling still runs as usual, so the views
+/// show exactly the spheres a shaded frame would draw.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugView {
+    /// The shaded scene.
+    #[default]
+    None,
+    /// Surface normals, mapped from -1..1 to 0..1 per axis.
+    Normals,
+    /// World position, repeating every 10 units per axis so seams and
+    /// misplaced spheres stand out.
+    WorldPosition,
+    /// `material_index` hashed to a color, so neighbouring indices differ
+    /// clearly.
+    MaterialIndex,
+    /// View depth from black at the near plane to white at the far plane.
+    LinearDepth,
+    /// Sphere fragments per pixel with the depth test off, from blue for one
+    /// through green and yellow to red for eight or more.
+    Overdraw,
+    /// Level of detail: mesh levels 0 to 3 in red, yellow, green and cyan,
+    /// impostors in magenta.
+    LodLevel,
+    /// Which draw list culling put each sphere in: green for the early
+    /// pass, orange for the late pass, blue for translucent spheres.
+    /// Culled spheres aren't drawn.
+    Culling,
+    /// Lights listed by the light cluster each sphere fragment falls in,
+    /// from blue for none through green and yellow to red for 32 or more.
+    LightCount,
+}
+
+impl DebugView {
+    /// Matches the constants in debug.wgsl.
+    fn shader_index(self) -> u32 {
+        match self {
+            DebugView::None => 0,
+            DebugView::Normals => 1,
+            DebugView::WorldPosition => 2,
+            DebugView::MaterialIndex => 3,
+            DebugView::LinearDepth => 4,
+            DebugView::Overdraw => 5,
+            DebugView::LodLevel => 6,
+            DebugView::Culling => 7,
+            DebugView::LightCount => 8,
+        }
+    }
+}
+
+/// Mirrors `DebugParams` in debug.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugUniform {
+    view: u32,
+    near: f32,
+    far: f32,
+    _padding: u32,
+}
+
+/// Mirrors `DrawInfo` in debug.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawInfo {
+    list: u32,
+    bucket: u32,
+}
+
+/// Mirrors `Params` in debug_resolve.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ResolveUniform {
+    heat_max: f32,
+    encode_srgb: u32,
+    _padding: [u32; 2],
+}
+
+/// Pipelines and targets of the debug views. The spheres are redrawn with
+/// a depth buffer of their own, so translucent spheres show like opaque
+/// ones, then the result is copied to the output target.
+pub(crate) struct DebugViews {
+    mesh_pipeline: wgpu::RenderPipeline,
+    impostor_pipeline: wgpu::RenderPipeline,
+    // Additive, without a depth test
+    mesh_overdraw_pipeline: wgpu::RenderPipeline,
+    impostor_overdraw_pipeline: wgpu::RenderPipeline,
+    params_buffer: wgpu::Buffer,
+    draw_info_stride: u64,
+    bind_group: wgpu::BindGroup,
+    depth_format: wgpu::TextureFormat,
+    depth_view: wgpu::TextureView,
+    resolve_buffer: wgpu::Buffer,
+    resolve_layout: wgpu::BindGroupLayout,
+    resolve_pipelines: FullscreenPipelines,
+}
+
+impl DebugViews {
+    /// `sphere_layouts` are the camera, sphere and environment bind group
+    /// layouts of the sphere passes, and `vertex_shader` the module with
+    /// their mesh vertex stage. `draw_commands` is the number of indirect
+    /// draws per frame, `buckets_per_list` of them per draw list.
+    pub fn new(
+        device: &wgpu::Device,
+        sphere_layouts: [&wgpu::BindGroupLayout; 3],
+        vertex_shader: &wgpu::ShaderModule,
+        depth_format: wgpu::TextureFormat,
+        (draw_commands, buckets_per_list): (usize, usize),
+        (width, height): (u32, u32),
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Debug View Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/brdf.wgsl"),
+                    "\n",
+                    include_str!("shaders/fragment.wgsl"),
+                    "\n",
+                    include_str!("shaders/impostor.wgsl"),
+                    "\n",
+                    include_str!("shaders/debug.wgsl"),
+                )
+                .into(),
+            ),
+        });
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug View Params Buffer"),
+            size: std::mem::size_of::<DebugUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // One DrawInfo per draw command, at offsets the dynamic binding accepts
+        let draw_info_stride = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let draw_infos: Vec<u8> = (0..draw_commands)
+            .flat_map(|command| {
+                let info = DrawInfo {
+                    list: (command / buckets_per_list) as u32,
+                    bucket: (command % buckets_per_list) as u32,
+                };
+                let mut bytes = bytemuck::bytes_of(&info).to_vec();
+                bytes.resize(draw_info_stride as usize, 0);
+                bytes
+            })
+            .collect();
+        let draw_info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Debug Draw Info Buffer"),
+            contents: &draw_infos,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        // Bindings 0 to 3 are the shadow group's, which this group replaces
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Debug View Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<DrawInfo>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Debug View Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &draw_info_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<DrawInfo>() as u64),
+                    }),
+                },
+            ],
+        });
+
+        let [camera_layout, sphere_layout, environment_layout] = sphere_layouts;
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug View Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, sphere_layout, environment_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipelines = |overdraw: bool| {
+            let depth_stencil = wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: !overdraw,
+                depth_compare: if overdraw {
+                    wgpu::CompareFunction::Always
+                } else {
+                    wgpu::CompareFunction::Less
+                },
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            };
+            let additive = wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            };
+            let target = wgpu::ColorTargetState {
+                format: HDR_FORMAT,
+                blend: overdraw.then_some(wgpu::BlendState {
+                    color: additive,
+                    alpha: additive,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            };
+            let mesh = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Mesh Debug View Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: vertex_shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[Vertex::layout()],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_debug"),
+                    targets: &[Some(target.clone())],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(depth_stencil.clone()),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            });
+            let impostor = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Impostor Debug View Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_impostor"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_impostor_debug"),
+                    targets: &[Some(target)],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(depth_stencil),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            });
+            (mesh, impostor)
+        };
+        let (mesh_pipeline, impostor_pipeline) = create_pipelines(false);
+        let (mesh_overdraw_pipeline, impostor_overdraw_pipeline) = create_pipelines(true);
+
+        let resolve_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Debug Resolve Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(include_str!("shaders/srgb.wgsl"), "\n", include_str!("shaders/debug_resolve.wgsl")).into(),
+            ),
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Resolve Params Buffer"),
+            size: std::mem::size_of::<ResolveUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let resolve_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Debug Resolve Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let resolve_pipelines = FullscreenPipelines::new(
+            device,
+            "Debug Resolve Pipeline",
+            resolve_shader,
+            &[&resolve_layout],
+            "fs_resolve",
+        );
+
+        Self {
+            mesh_pipeline,
+            impostor_pipeline,
+            mesh_overdraw_pipeline,
+            impostor_overdraw_pipeline,
+            params_buffer,
+            draw_info_stride,
+            bind_group,
+            depth_format,
+            depth_view: create_depth_view(device, depth_format, width, height),
+            resolve_buffer,
+            resolve_layout,
+            resolve_pipelines,
+        }
+    }
+
+    /// Mesh and impostor pipelines for `view`.
+    pub fn pipelines(&self, view: DebugView) -> (&wgpu::RenderPipeline, &wgpu::RenderPipeline) {
+        if view == DebugView::Overdraw {
+            (&self.mesh_overdraw_pipeline, &self.impostor_overdraw_pipeline)
+        } else {
+            (&self.mesh_pipeline, &self.impostor_pipeline)
+        }
+    }
+
+    /// Bind group 3 of the debug pipelines.
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Dynamic offset of bind group 3 for draw command `command`.
+    pub fn draw_info_offset(&self, command: usize) -> u32 {
+        (command as u64 * self.draw_info_stride) as u32
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.depth_view = create_depth_view(device, self.depth_format, width, height);
+    }
+
+    /// Starts the debug pass into `color_view` with cleared targets.
+    /// `near` and `far` are the camera's clip planes, with a finite far
+    /// plane for the linear depth view to divide by.
+    pub fn begin_pass<'a>(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &'a mut wgpu::CommandEncoder,
+        color_view: &wgpu::TextureView,
+        view: DebugView,
+        (near, far): (f32, f32),
+    ) -> wgpu::RenderPass<'a> {
+        let uniform = DebugUniform {
+            view: view.shader_index(),
+            near,
+            far,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Debug View Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: color_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        })
+    }
+
+    /// Copies what the debug pass drew into `color_view` to `output`.
+    pub fn encode_resolve(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        color_view: &wgpu::TextureView,
+        (view, output): (DebugView, &PostTarget),
+    ) {
+        let uniform = ResolveUniform {
+            heat_max: match view {
+                DebugView::Overdraw => OVERDRAW_HEAT_MAX,
+                DebugView::LightCount => LIGHT_COUNT_HEAT_MAX,
+                _ => 0.0,
+            },
+            encode_srgb: output.encode_srgb as u32,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.resolve_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Debug Resolve Bind Group"),
+            layout: &self.resolve_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.resolve_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(color_view),
+                },
+            ],
+        });
+
+        let pipeline = self.resolve_pipelines.get(device, output.format);
+        let mut pass = begin_fullscreen_pass(encoder, "Debug Resolve Pass", output.view);
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_depth_view(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("Debug View Depth Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
@@ -0,0 +1,690 @@
+use half::f16;
+use std::fmt;
+use std::path::Path;
+use wgpu::util::DeviceExt;
+
+use crate::tonemap::HDR_FORMAT;
+
+// Face sizes of the baked cubemaps
+const ENVIRONMENT_SIZE: u32 = 256;
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+/// Roughness 0 to 1 in even steps. Must match PREFILTERED_LEVELS in fragment.wgsl.
+const PREFILTERED_LEVELS: u32 = 5;
+/// Must match BRDF_LUT_SIZE in ibl.wgsl.
+const BRDF_LUT_SIZE: u32 = 128;
+
+/// Equirectangular map of linear radiance, the source for image-based
+/// lighting. Row 0 is straight up.
+pub struct EnvironmentMap {
+    width: u32,
+    height: u32,
+    texels: Vec<[f32; 3]>,
+}
+
+/// Rejected environment maps.
+#[derive(Debug)]
+pub enum EnvironmentError {
+    /// The file couldn't be read or decoded.
+    Image(image::ImageError),
+    /// The map is zero texels wide or high.
+    Empty { width: u32, height: u32 },
+    /// `texels` doesn't hold `width * height` texels.
+    SizeMismatch { width: u32, height: u32, texels: usize },
+}
+
+impl fmt::Display for EnvironmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Image(error) => write!(f, "environment map could not be loaded: {error}"),
+            Self::Empty { width, height } => write!(f, "environment map is empty ({width}x{height})"),
+            Self::SizeMismatch { width, height, texels } => {
+                write!(f, "{texels} texel(s) given for a {width}x{height} environment map")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EnvironmentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Image(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<image::ImageError> for EnvironmentError {
+    fn from(error: image::ImageError) -> Self {
+        Self::Image(error)
+    }
+}
+
+impl EnvironmentMap {
+    /// `texels` are `width * height` radiance values, row by row.
+    pub fn new(width: u32, height: u32, texels: Vec<[f32; 3]>) -> Result<Self, EnvironmentError> {
+        if width == 0 || height == 0 {
+            return Err(EnvironmentError::Empty { width, height });
+        }
+        if texels.len() != width as usize * height as usize {
+            return Err(EnvironmentError::SizeMismatch {
+                width,
+                height,
+                texels: texels.len(),
+            });
+        }
+        Ok(Self { width, height, texels })
+    }
+
+    /// Reads a Radiance `.hdr` file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, EnvironmentError> {
+        let image = image::open(path)?.into_rgb32f();
+        let (width, height) = image.dimensions();
+        Self::new(width, height, image.pixels().map(|pixel| pixel.0).collect())
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Box-filtered levels down to 1x1, starting from the first one no wider
+    /// than `max_width`.
+    fn mip_chain(&self, max_width: u32) -> Vec<(u32, u32, Vec<[f32; 3]>)> {
+        let mut levels = vec![(self.width, self.height, self.texels.clone())];
+        loop {
+            let (width, height, texels) = levels.last().unwrap();
+            if *width == 1 && *height == 1 {
+                break;
+            }
+            let (width, height) = (*width, *height);
+            let next_width = (width / 2).max(1);
+            let next_height = (height / 2).max(1);
+            let mut next = Vec::with_capacity((next_width * next_height) as usize);
+            for y in 0..next_height {
+                for x in 0..next_width {
+                    let mut sum = [0.0; 3];
+                    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let sx = (x * 2 + dx).min(width - 1);
+                        let sy = (y * 2 + dy).min(height - 1);
+                        let texel = texels[(sy * width + sx) as usize];
+                        for channel in 0..3 {
+                            sum[channel] += texel[channel] * 0.25;
+                        }
+                    }
+                    next.push(sum);
+                }
+            }
+            levels.push((next_width, next_height, next));
+        }
+
+        let first = levels.iter().position(|(width, _, _)| *width <= max_width).unwrap_or(0);
+        levels.split_off(first)
+    }
+}
+
+impl Default for EnvironmentMap {
+    /// Soft sky over a dim ground, so scenes without an HDR file still get
+    /// ambient light from every direction.
+    fn default() -> Self {
+        let (width, height) = (64, 32);
+        let zenith = [0.25, 0.45, 0.9];
+        let horizon = [0.9, 0.95, 1.0];
+        let ground = [0.3, 0.27, 0.24];
+
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            let elevation = 1.0 - 2.0 * (y as f32 + 0.5) / height as f32;
+            let texel = if elevation >= 0.0 {
+                let t = elevation.sqrt();
+                [0, 1, 2].map(|c| horizon[c] + (zenith[c] - horizon[c]) * t)
+            } else {
+                let t = (-elevation * 8.0).min(1.0);
+                [0, 1, 2].map(|c| horizon[c] * 0.5 + (ground[c] - horizon[c] * 0.5) * t)
+            };
+            texels.extend(std::iter::repeat_n(texel, width as usize));
+        }
+
+        Self { width, height, texels }
+    }
+}
+
+/// Mirrors `BakeParams` in ibl.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BakeUniform {
+    face: u32,
+    roughness: f32,
+    source_level: f32,
+    target_size: f32,
+}
+
+/// Image-based lighting baked from an [`EnvironmentMap`]: the environment
+/// cubemap, its diffuse irradiance, a GGX-prefiltered mip chain and the
+/// split-sum BRDF lookup table. The lighting shaders read the last three
+/// through [`Self::bind_group`].
+pub(crate) struct EnvironmentLighting {
+    baker: Baker,
+    bind_group: wgpu::BindGroup,
+}
+
+impl EnvironmentLighting {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, map: &EnvironmentMap) -> Self {
+        let baker = Baker::new(device, queue);
+        let bind_group = baker.bake(device, queue, map);
+        Self { baker, bind_group }
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.baker.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Re-bakes every environment-dependent texture from `map`.
+    pub fn set_map(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, map: &EnvironmentMap) {
+        self.bind_group = self.baker.bake(device, queue, map);
+    }
+}
+
+/// One fullscreen draw per cube face of `target` at mip `level`
+struct BakeDraw<'a> {
+    pipeline: &'a wgpu::RenderPipeline,
+    target: &'a wgpu::Texture,
+    level: u32,
+    roughness: f32,
+    source_level: f32,
+    from_equirect: bool,
+}
+
+struct Baker {
+    equirect_layout: wgpu::BindGroupLayout,
+    cube_layout: wgpu::BindGroupLayout,
+    equirect_pipeline: wgpu::RenderPipeline,
+    irradiance_pipeline: wgpu::RenderPipeline,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    bake_sampler: wgpu::Sampler,
+    sampler: wgpu::Sampler,
+    brdf_lut: wgpu::TextureView,
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl Baker {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Environment Bake Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(include_str!("shaders/brdf.wgsl"), "\n", include_str!("shaders/ibl.wgsl")).into(),
+            ),
+        });
+
+        let params_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let sampler_entry = wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        let equirect_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Equirect Bake Bind Group Layout"),
+            entries: &[
+                params_entry,
+                sampler_entry,
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let cube_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Cube Bake Bind Group Layout"),
+            entries: &[
+                params_entry,
+                sampler_entry,
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let create_pipeline = |label: &str, layouts: &[&wgpu::BindGroupLayout], entry_point: &str, format: wgpu::TextureFormat| {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: layouts,
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_fullscreen"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(format.into())],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        let equirect_pipeline = create_pipeline("Equirect To Cube Pipeline", &[&equirect_layout], "fs_equirect_to_cube", HDR_FORMAT);
+        let irradiance_pipeline = create_pipeline("Irradiance Pipeline", &[&cube_layout], "fs_irradiance", HDR_FORMAT);
+        let prefilter_pipeline = create_pipeline("Prefilter Pipeline", &[&cube_layout], "fs_prefilter", HDR_FORMAT);
+        let brdf_pipeline = create_pipeline("BRDF LUT Pipeline", &[], "fs_brdf_lut", wgpu::TextureFormat::Rg16Float);
+
+        // Equirect maps wrap horizontally; cube lookups ignore address modes
+        let bake_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Bake Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        // The LUT only depends on the BRDF, so it is baked once
+        let brdf_lut = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("BRDF LUT"),
+                size: wgpu::Extent3d {
+                    width: BRDF_LUT_SIZE,
+                    height: BRDF_LUT_SIZE,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rg16Float,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("BRDF LUT Encoder"),
+        });
+        {
+            let mut pass = begin_bake_pass(&mut encoder, &brdf_lut);
+            pass.set_pipeline(&brdf_pipeline);
+            pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Environment Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        Self {
+            equirect_layout,
+            cube_layout,
+            equirect_pipeline,
+            irradiance_pipeline,
+            prefilter_pipeline,
+            bake_sampler,
+            sampler,
+            brdf_lut,
+            bind_group_layout,
+        }
+    }
+
+    /// Renders the cubemaps for `map` and returns the bind group reading them.
+    fn bake(&self, device: &wgpu::Device, queue: &wgpu::Queue, map: &EnvironmentMap) -> wgpu::BindGroup {
+        // No need to keep more equirect detail than the cube faces can show
+        let levels = map.mip_chain(ENVIRONMENT_SIZE * 4);
+        let (base_width, base_height, _) = levels[0];
+        let equirect = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Equirect Environment Texture"),
+            size: wgpu::Extent3d {
+                width: base_width,
+                height: base_height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: levels.len() as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        for (level, (width, height, texels)) in levels.iter().enumerate() {
+            let data: Vec<f16> = texels
+                .iter()
+                .flat_map(|&[r, g, b]| [r, g, b, 1.0].map(f16::from_f32))
+                .collect();
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &equirect,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(&data),
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width * 8),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width: *width,
+                    height: *height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        let equirect_view = equirect.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let environment_levels = ENVIRONMENT_SIZE.ilog2() + 1;
+        let environment = create_cube_texture(device, "Environment Cubemap", ENVIRONMENT_SIZE, environment_levels);
+        let irradiance = create_cube_texture(device, "Irradiance Cubemap", IRRADIANCE_SIZE, 1);
+        let prefiltered = create_cube_texture(device, "Prefiltered Cubemap", PREFILTERED_SIZE, PREFILTERED_LEVELS);
+
+        // One uniform slot per draw, selected with a dynamic offset. Equirect
+        // mips are picked so their texels match the cube level being drawn.
+        let equirect_bias = (base_width as f32 / (4 * ENVIRONMENT_SIZE) as f32).log2();
+        let mut draws = Vec::new();
+        for level in 0..environment_levels {
+            draws.push(BakeDraw {
+                pipeline: &self.equirect_pipeline,
+                target: &environment,
+                level,
+                roughness: 0.0,
+                source_level: (level as f32 + equirect_bias).max(0.0),
+                from_equirect: true,
+            });
+        }
+        draws.push(BakeDraw {
+            pipeline: &self.irradiance_pipeline,
+            target: &irradiance,
+            level: 0,
+            roughness: 0.0,
+            source_level: 0.0,
+            from_equirect: false,
+        });
+        for level in 0..PREFILTERED_LEVELS {
+            draws.push(BakeDraw {
+                pipeline: &self.prefilter_pipeline,
+                target: &prefiltered,
+                level,
+                roughness: level as f32 / (PREFILTERED_LEVELS - 1) as f32,
+                source_level: 0.0,
+                from_equirect: false,
+            });
+        }
+
+        let stride = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let uniforms: Vec<u8> = draws
+            .iter()
+            .flat_map(|draw| {
+                (0..6).flat_map(move |face| {
+                    let uniform = BakeUniform {
+                        face,
+                        roughness: draw.roughness,
+                        source_level: draw.source_level,
+                        target_size: (draw.target.width() >> draw.level).max(1) as f32,
+                    };
+                    let mut slot = bytemuck::bytes_of(&uniform).to_vec();
+                    slot.resize(stride as usize, 0);
+                    slot
+                })
+            })
+            .collect();
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Bake Params Buffer"),
+            contents: &uniforms,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let params_binding = wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+            buffer: &params_buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new(std::mem::size_of::<BakeUniform>() as u64),
+        });
+
+        let equirect_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Equirect Bake Bind Group"),
+            layout: &self.equirect_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_binding.clone(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.bake_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&equirect_view),
+                },
+            ],
+        });
+
+        let environment_view = create_cube_view(&environment);
+        let cube_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Cube Bake Bind Group"),
+            layout: &self.cube_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_binding,
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.bake_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&environment_view),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Bake Encoder"),
+        });
+        for (index, draw) in draws.iter().enumerate() {
+            let bind_group = if draw.from_equirect {
+                &equirect_bind_group
+            } else {
+                &cube_bind_group
+            };
+            for face in 0..6 {
+                let view = draw.target.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Environment Bake Face View"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_mip_level: draw.level,
+                    mip_level_count: Some(1),
+                    base_array_layer: face,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                });
+                let mut pass = begin_bake_pass(&mut encoder, &view);
+                let slot = (index * 6) as u64 + face as u64;
+                pass.set_pipeline(draw.pipeline);
+                pass.set_bind_group(0, bind_group, &[(slot * stride) as u32]);
+                pass.draw(0..3, 0..1);
+            }
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Environment Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&create_cube_view(&irradiance)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&create_cube_view(&prefiltered)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.brdf_lut),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+}
+
+fn create_cube_texture(device: &wgpu::Device, label: &str, size: u32, mip_level_count: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+fn create_cube_view(texture: &wgpu::Texture) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        ..Default::default()
+    })
+}
+
+fn begin_bake_pass<'a>(encoder: &'a mut wgpu::CommandEncoder, target: &wgpu::TextureView) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Environment Bake Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_rejects_empty_maps() {
+        assert!(matches!(
+            EnvironmentMap::new(0, 4, Vec::new()),
+            Err(EnvironmentError::Empty { width: 0, height: 4 })
+        ));
+        assert!(matches!(
+            EnvironmentMap::new(4, 0, Vec::new()),
+            Err(EnvironmentError::Empty { width: 4, height: 0 })
+        ));
+    }
+
+    #[test]
+    fn new_rejects_texel_count_mismatch() {
+        assert!(matches!(
+            EnvironmentMap::new(4, 2, vec![[0.0; 3]; 7]),
+            Err(EnvironmentError::SizeMismatch { width: 4, height: 2, texels: 7 })
+        ));
+        let map = EnvironmentMap::new(4, 2, vec![[0.0; 3]; 8]).unwrap();
+        assert_eq!((map.width(), map.height()), (4, 2));
+    }
+
+    #[test]
+    fn default_map_is_valid() {
+        let map = EnvironmentMap::default();
+        assert!(EnvironmentMap::new(map.width, map.height, map.texels).is_ok());
+    }
+}
@@ -0,0 +1,241 @@
+use wgpu::util::DeviceExt;
+
+/// Hierarchical max-depth pyramid built from the scene depth buffer, used by
+/// the culling pass to reject spheres hidden behind nearer geometry.
+///
+/// Levels are packed into one storage buffer rather than a mipmapped
+/// texture: the GL backend emulates texture views by changing the texture's
+/// base level, which drops image stores to every other mip.
+pub(crate) struct DepthPyramid {
+    init_pipeline: wgpu::ComputePipeline,
+    downsample_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    buffer: wgpu::Buffer,
+    levels: Vec<PyramidLevel>,
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+#[derive(Clone, Copy)]
+struct PyramidLevel {
+    offset: u32,
+    width: u32,
+    height: u32,
+}
+
+/// Mirrors `LevelParams` in hiz.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LevelUniform {
+    src_offset: u32,
+    src_width: u32,
+    src_height: u32,
+    dst_offset: u32,
+    dst_width: u32,
+    dst_height: u32,
+}
+
+impl DepthPyramid {
+    pub fn new(device: &wgpu::Device, depth_view: &wgpu::TextureView, width: u32, height: u32) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Depth Pyramid Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/hiz.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Depth Pyramid Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth Pyramid Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let init_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Depth Pyramid Init Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("init"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let downsample_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Depth Pyramid Downsample Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("downsample"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let (buffer, levels, bind_groups) =
+            Self::create_targets(device, &bind_group_layout, depth_view, width, height);
+
+        Self {
+            init_pipeline,
+            downsample_pipeline,
+            bind_group_layout,
+            buffer,
+            levels,
+            bind_groups,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, depth_view: &wgpu::TextureView, width: u32, height: u32) {
+        let (buffer, levels, bind_groups) =
+            Self::create_targets(device, &self.bind_group_layout, depth_view, width, height);
+        self.buffer = buffer;
+        self.levels = levels;
+        self.bind_groups = bind_groups;
+    }
+
+    /// Every level, back to back, starting with the largest.
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Size of level 0 in texels.
+    pub fn size(&self) -> [u32; 2] {
+        [self.levels[0].width, self.levels[0].height]
+    }
+
+    pub fn level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Depth Pyramid Pass"),
+            timestamp_writes: None,
+        });
+
+        for (index, (level, bind_group)) in self.levels.iter().zip(&self.bind_groups).enumerate() {
+            let pipeline = if index == 0 {
+                &self.init_pipeline
+            } else {
+                &self.downsample_pipeline
+            };
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(level.width.div_ceil(8), level.height.div_ceil(8), 1);
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::Buffer, Vec<PyramidLevel>, Vec<wgpu::BindGroup>) {
+        // Largest power of two not above the depth buffer size
+        let base_width: u32 = 1 << width.max(1).ilog2();
+        let base_height: u32 = 1 << height.max(1).ilog2();
+        let level_count = base_width.max(base_height).ilog2() + 1;
+
+        let mut levels = Vec::with_capacity(level_count as usize);
+        let mut offset = 0;
+        for level in 0..level_count {
+            let width = (base_width >> level).max(1);
+            let height = (base_height >> level).max(1);
+            levels.push(PyramidLevel { offset, width, height });
+            offset += width * height;
+        }
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Depth Pyramid Buffer"),
+            size: offset as u64 * std::mem::size_of::<f32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        // One uniform slot per level, each at a legal binding offset
+        let stride = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let uniforms: Vec<u8> = levels
+            .iter()
+            .enumerate()
+            .flat_map(|(index, dst)| {
+                let src = levels[index.saturating_sub(1)];
+                let uniform = LevelUniform {
+                    src_offset: src.offset,
+                    src_width: src.width,
+                    src_height: src.height,
+                    dst_offset: dst.offset,
+                    dst_width: dst.width,
+                    dst_height: dst.height,
+                };
+                let mut slot = bytemuck::bytes_of(&uniform).to_vec();
+                slot.resize(stride as usize, 0);
+                slot
+            })
+            .collect();
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Depth Pyramid Level Buffer"),
+            contents: &uniforms,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bind_groups = (0..levels.len() as u64)
+            .map(|index| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Depth Pyramid Bind Group"),
+                    layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(depth_view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                                buffer: &uniform_buffer,
+                                offset: index * stride,
+                                size: wgpu::BufferSize::new(std::mem::size_of::<LevelUniform>() as u64),
+                            }),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        (buffer, levels, bind_groups)
+    }
+}
@@ -0,0 +1,19 @@
+pub mod ambient_occlusion;
+pub mod bloom;
+pub mod cluster;
+pub mod culling;
+pub mod debug;
+pub mod environment;
+mod hiz;
+pub mod light;
+pub mod mesh;
+pub mod msaa;
+mod picking;
+pub mod post;
+pub mod renderer;
+pub mod selection;
+pub mod shadow;
+pub mod ssao;
+pub mod taa;
+pub mod tonemap;
+mod transparency;
@@ -0,0 +1,130 @@
+use glam::Vec3;
+
+/// A punctual light, following the glTF `KHR_lights_punctual` conventions:
+/// point and spot intensities are in candela and fall off with the inverse
+/// square of the distance, directional intensity is in lux.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Light {
+    Point {
+        position: Vec3,
+        color: Vec3,
+        intensity: f32,
+        /// Distance at which the light fades out completely. `None` for no cutoff.
+        range: Option<f32>,
+    },
+    Directional {
+        /// Direction the light travels in.
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+    },
+    Spot {
+        position: Vec3,
+        /// Direction the cone points in.
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        range: Option<f32>,
+        /// Angle from the axis, in radians, where the falloff starts.
+        inner_cone_angle: f32,
+        /// Angle from the axis, in radians, where the light reaches zero.
+        outer_cone_angle: f32,
+    },
+}
+
+impl Light {
+    const POINT: u32 = 0;
+    const DIRECTIONAL: u32 = 1;
+    const SPOT: u32 = 2;
+
+    pub(crate) fn to_gpu(self) -> GpuLight {
+        match self {
+            Light::Point { position, color, intensity, range } => GpuLight {
+                position: position.to_array(),
+                range: range.unwrap_or(0.0),
+                direction: [0.0; 3],
+                kind: Self::POINT,
+                color: color.to_array(),
+                intensity,
+                angle_scale: 0.0,
+                angle_offset: 0.0,
+                shadow: GpuLight::NO_SHADOW,
+                _padding: 0,
+            },
+            Light::Directional { direction, color, intensity } => GpuLight {
+                position: [0.0; 3],
+                range: 0.0,
+                direction: direction.normalize_or_zero().to_array(),
+                kind: Self::DIRECTIONAL,
+                color: color.to_array(),
+                intensity,
+                angle_scale: 0.0,
+                angle_offset: 0.0,
+                shadow: GpuLight::NO_SHADOW,
+                _padding: 0,
+            },
+            Light::Spot {
+                position,
+                direction,
+                color,
+                intensity,
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            } => {
+                // Smooth cone falloff as a single multiply-add in the shader
+                let cos_outer = outer_cone_angle.cos();
+                let cos_inner = inner_cone_angle.min(outer_cone_angle).cos();
+                let angle_scale = 1.0 / (cos_inner - cos_outer).max(0.001);
+                GpuLight {
+                    position: position.to_array(),
+                    range: range.unwrap_or(0.0),
+                    direction: direction.normalize_or_zero().to_array(),
+                    kind: Self::SPOT,
+                    color: color.to_array(),
+                    intensity,
+                    angle_scale,
+                    angle_offset: -cos_outer * angle_scale,
+                    shadow: GpuLight::NO_SHADOW,
+                    _padding: 0,
+                }
+            }
+        }
+    }
+}
+
+/// Handle returned by [`SphereRenderer::add_light`](crate::renderer::SphereRenderer::add_light).
+/// Stays valid until the light is removed; ids are never reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LightId(pub(crate) u32);
+
+/// Mirrors `Light` in fragment.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct GpuLight {
+    position: [f32; 3],
+    range: f32,
+    direction: [f32; 3],
+    kind: u32,
+    color: [f32; 3],
+    intensity: f32,
+    angle_scale: f32,
+    angle_offset: f32,
+    /// Index of the light's shadow cube map, or one of the constants below.
+    pub shadow: u32,
+    _padding: u32,
+}
+
+impl GpuLight {
+    pub const NO_SHADOW: u32 = u32::MAX;
+    pub const CASCADED_SHADOW: u32 = u32::MAX - 1;
+}
+
+/// Precedes the lights in the light buffer, mirrors the head of `Lights` in
+/// fragment.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(crate) struct LightHeader {
+    pub count: u32,
+    pub _padding: [u32; 3],
+}
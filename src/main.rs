@@ -7,12 +7,25 @@ use winit::{
 use glam::Vec3;
 use std::sync::Arc;
 
-mod renderer;
-use renderer::{Camera, SphereRenderer, Sphere};
+use pbr_spheres::debug::DebugView;
+use pbr_spheres::environment::EnvironmentMap;
+use pbr_spheres::light::Light;
+use pbr_spheres::post::{PostEffect, ShaderEffect};
+use pbr_spheres::renderer::{Camera, Material, RenderMode, SphereRenderer, Sphere};
+use pbr_spheres::tonemap::ToneMapping;
+
+// `post.params[0].x` is the strength
+const VIGNETTE_SHADER: &str = "
+fn effect(in: PostInput) -> vec4<f32> {
+    let color = textureSampleLevel(input_texture, input_sampler, in.uv, 0.0);
+    let offset = in.uv - 0.5;
+    let falloff = clamp(1.0 - dot(offset, offset) * post.params[0].x, 0.0, 1.0);
+    return vec4<f32>(color.rgb * falloff, color.a);
+}
+";
 
 fn create_test_spheres() -> Vec<Sphere> {
     let mut spheres = Vec::new();
-    let mut rng = rand::thread_rng();
 
     for i in 0..1_000_000 {
         let sphere = Sphere {
@@ -30,6 +43,27 @@ fn create_test_spheres() -> Vec<Sphere> {
     spheres
 }
 
+// 10x10 grid: metallic steps along one axis, roughness along the other
+fn create_test_materials() -> Vec<Material> {
+    (0..100)
+        .map(|i| {
+            let metallic = (i / 10) as f32 / 9.0;
+            let roughness = ((i % 10) as f32 / 9.0).max(0.05);
+            let hue = i as f32 * 0.61803;
+            Material {
+                base_color: [
+                    0.5 + 0.5 * (hue * std::f32::consts::TAU).cos(),
+                    0.5 + 0.5 * ((hue + 1.0 / 3.0) * std::f32::consts::TAU).cos(),
+                    0.5 + 0.5 * ((hue + 2.0 / 3.0) * std::f32::consts::TAU).cos(),
+                    1.0,
+                ],
+                metallic_roughness: [metallic, roughness],
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
 async fn run(event_loop: EventLoop<()>, window: Window) {
     let size = window.inner_size();
 
@@ -39,7 +73,7 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
         backend_options: wgpu::BackendOptions::default(),
     });
 
-    let surface = unsafe { instance.create_surface(&window) }.unwrap();
+    let surface = instance.create_surface(&window).unwrap();
     let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions {
         power_preference: wgpu::PowerPreference::HighPerformance,
         compatible_surface: Some(&surface),
@@ -87,67 +121,196 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
         device.clone(),
         queue.clone(),
         &config,
+        RenderMode::Impostor,
     );
 
+    // Optional equirectangular .hdr for ambient lighting
+    if let Some(path) = std::env::args().nth(1) {
+        match EnvironmentMap::load(&path) {
+            Ok(map) => renderer.set_environment(&map),
+            Err(e) => eprintln!("Failed to load environment {path}: {e}"),
+        }
+    }
+
+    renderer.add_light(Light::Directional {
+        direction: Vec3::new(-0.4, -1.0, 0.6),
+        color: Vec3::new(1.0, 0.95, 0.85),
+        intensity: 3.0,
+    });
+    let lamp = renderer.add_light(Light::Point {
+        position: Vec3::ZERO,
+        color: Vec3::new(1.0, 0.6, 0.3),
+        intensity: 2000.0,
+        range: Some(80.0),
+    });
+    renderer.set_light_shadows(lamp, true);
+
+    // Example of a user post effect, darkening the corners after tone mapping
+    let mut vignette = ShaderEffect::new(&device, "Vignette", VIGNETTE_SHADER).unwrap();
+    vignette.set_params([[1.2, 0.0, 0.0, 0.0], [0.0; 4], [0.0; 4], [0.0; 4]]);
+    vignette.set_enabled(false);
+    let vignette = renderer.add_post_effect(vignette);
+
+    let mut materials = create_test_materials();
+    renderer.update_material_data(&materials).unwrap();
     let spheres = create_test_spheres();
-    renderer.update_sphere_data(&spheres);
+    renderer.update_sphere_data(&spheres).unwrap();
+    let mut cursor = (0, 0);
+    let mut pending_click = None;
 
     event_loop.run(|event, target| {
         match event {
-            Event::WindowEvent { window_id, event } => {
-                if window_id == window.id() {
-                    match event {
-                        WindowEvent::CloseRequested => target.exit(),
-                        WindowEvent::KeyboardInput { 
-                            event: KeyEvent { 
-                                physical_key: PhysicalKey::Code(keycode),
-                                state: ElementState::Pressed,
-                                ..
-                            },
+            Event::WindowEvent { window_id, event } if window_id == window.id() => {
+                match event {
+                    WindowEvent::CloseRequested => target.exit(),
+                    WindowEvent::KeyboardInput { 
+                        event: KeyEvent { 
+                            physical_key: PhysicalKey::Code(keycode),
+                            state: ElementState::Pressed,
                             ..
-                        } => {
-                            match keycode {
-                                KeyCode::KeyW => camera.move_forward(1.0),
-                                KeyCode::KeyS => camera.move_forward(-1.0),
-                                KeyCode::KeyA => camera.move_right(-1.0),
-                                KeyCode::KeyD => camera.move_right(1.0),
-                                _ => (),
+                        },
+                        ..
+                    } => {
+                        match keycode {
+                            KeyCode::KeyW => camera.move_forward(1.0),
+                            KeyCode::KeyS => camera.move_forward(-1.0),
+                            KeyCode::KeyA => camera.move_right(-1.0),
+                            KeyCode::KeyD => camera.move_right(1.0),
+                            KeyCode::KeyO => {
+                                let enabled = !renderer.occlusion_culling();
+                                renderer.set_occlusion_culling(enabled);
+                            }
+                            KeyCode::KeyC => println!("{:?}", renderer.cull_stats()),
+                            KeyCode::KeyK => {
+                                let mut settings = renderer.shadow_settings();
+                                settings.show_cascades = !settings.show_cascades;
+                                renderer.set_shadow_settings(settings);
+                            }
+                            KeyCode::KeyP => {
+                                let mut settings = renderer.ssao_settings();
+                                settings.enabled = !settings.enabled;
+                                renderer.set_ssao_settings(settings);
+                            }
+                            KeyCode::KeyM => {
+                                // Next supported sample count, wrapping back to 1
+                                let current = renderer.sample_count();
+                                let supported = renderer.supported_sample_counts(&adapter);
+                                let next = supported.into_iter().find(|&n| n > current).unwrap_or(1);
+                                match renderer.set_sample_count(&adapter, next) {
+                                    Ok(()) => println!("MSAA: {next}x"),
+                                    Err(e) => eprintln!("{e}"),
+                                }
+                            }
+                            KeyCode::KeyJ => {
+                                let mut settings = renderer.taa_settings();
+                                settings.enabled = !settings.enabled;
+                                println!("TAA: {}", if settings.enabled { "on" } else { "off" });
+                                renderer.set_taa_settings(settings);
+                            }
+                            KeyCode::KeyB => {
+                                let mut settings = renderer.bloom_settings();
+                                settings.enabled = !settings.enabled;
+                                renderer.set_bloom_settings(settings);
                             }
+                            KeyCode::KeyV => {
+                                if let Some(effect) = renderer.post_effect_mut::<ShaderEffect>(vignette) {
+                                    let enabled = !effect.enabled();
+                                    effect.set_enabled(enabled);
+                                }
+                            }
+                            KeyCode::KeyF => {
+                                // Fades the metallic half of the materials in and out
+                                for material in &mut materials[50..] {
+                                    material.base_color[3] = if material.is_translucent() { 1.0 } else { 0.3 };
+                                }
+                                renderer.update_material_data(&materials).unwrap();
+                            }
+                            KeyCode::KeyT => {
+                                let next = match renderer.tone_mapping() {
+                                    ToneMapping::Aces => ToneMapping::Reinhard,
+                                    ToneMapping::Reinhard => ToneMapping::AgX,
+                                    ToneMapping::AgX => ToneMapping::PbrNeutral,
+                                    ToneMapping::PbrNeutral => ToneMapping::Aces,
+                                };
+                                println!("Tone mapping: {:?}", next);
+                                renderer.set_tone_mapping(next);
+                            }
+                            KeyCode::KeyG => {
+                                let next = match renderer.debug_view() {
+                                    DebugView::None => DebugView::Normals,
+                                    DebugView::Normals => DebugView::WorldPosition,
+                                    DebugView::WorldPosition => DebugView::MaterialIndex,
+                                    DebugView::MaterialIndex => DebugView::LinearDepth,
+                                    DebugView::LinearDepth => DebugView::Overdraw,
+                                    DebugView::Overdraw => DebugView::LodLevel,
+                                    DebugView::LodLevel => DebugView::Culling,
+                                    DebugView::Culling => DebugView::LightCount,
+                                    DebugView::LightCount => DebugView::None,
+                                };
+                                println!("Debug view: {:?}", next);
+                                renderer.set_debug_view(next);
+                            }
+                            KeyCode::KeyL => {
+                                let mut settings = renderer.selection_settings();
+                                settings.tint_strength = if settings.tint_strength > 0.0 { 0.0 } else { 0.35 };
+                                renderer.set_selection_settings(settings);
+                            }
+                            KeyCode::Minus => renderer.set_exposure(renderer.exposure() - 0.5),
+                            KeyCode::Equal => renderer.set_exposure(renderer.exposure() + 0.5),
+                            _ => (),
                         }
-                        WindowEvent::Resized(new_size) => {
-                            if new_size.width > 0 && new_size.height > 0 {
-                                config.width = new_size.width;
-                                config.height = new_size.height;
+                    }
+                    WindowEvent::CursorMoved { position, .. } => {
+                        cursor = (position.x as u32, position.y as u32);
+                    }
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    } => pending_click = Some(cursor),
+                    WindowEvent::Resized(new_size) if new_size.width > 0 && new_size.height > 0 => {
+                        config.width = new_size.width;
+                        config.height = new_size.height;
+                        surface.configure(&device, &config);
+                        renderer.resize(new_size.width, new_size.height);
+                        camera.update_aspect(new_size.width as f32 / new_size.height as f32);
+                    }
+                    WindowEvent::RedrawRequested => {
+                        match surface.get_current_texture() {
+                            Ok(frame) => {
+                                let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
+                                renderer.render(
+                                    &view,
+                                    camera.view_matrix(),
+                                    camera.projection_matrix(),
+                                    camera.position(),
+                                );
+                                frame.present();
+                            }
+                            Err(wgpu::SurfaceError::Lost) => {
                                 surface.configure(&device, &config);
-                                camera.update_aspect(new_size.width as f32 / new_size.height as f32);
                             }
+                            Err(wgpu::SurfaceError::OutOfMemory) => target.exit(),
+                            Err(e) => eprintln!("{:?}", e),
                         }
-                        _ => (),
                     }
+                    _ => (),
                 }
             }
             Event::AboutToWait => {
-                window.request_redraw();
-            }
-            Event::WindowEvent { 
-                window_id,
-                event: WindowEvent::RedrawRequested,
-            } if window_id == window.id() => {
-                match surface.get_current_texture() {
-                    Ok(frame) => {
-                        renderer.render(
-                            camera.view_matrix(),
-                            camera.projection_matrix(),
-                            camera.position(),
-                        );
-                        frame.present();
-                    }
-                    Err(wgpu::SurfaceError::Lost) => {
-                        surface.configure(&device, &config);
+                // Picks wait for the GPU, so they're resolved here rather than
+                // while input events are still being handled
+                if let Some((x, y)) = pending_click.take() {
+                    match pollster::block_on(renderer.pick(x, y)) {
+                        // Clicking toggles a sphere's selection, the background clears it
+                        Some(index) => {
+                            println!("Sphere {index}: {:?}", spheres[index as usize]);
+                            renderer.toggle_selected(index);
+                        }
+                        None => renderer.clear_selection(),
                     }
-                    Err(wgpu::SurfaceError::OutOfMemory) => target.exit(),
-                    Err(e) => eprintln!("{:?}", e),
                 }
+                window.request_redraw();
             }
             _ => (),
         }
@@ -0,0 +1,270 @@
+use glam::Vec3;
+use std::collections::HashMap;
+use wgpu::util::DeviceExt;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+impl Vertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+        0 => Float32x3, // position
+        1 => Float32x3, // normal
+    ];
+
+    pub fn layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as u64,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+
+    fn on_unit_sphere(direction: Vec3) -> Self {
+        let n = direction.normalize();
+        Self {
+            position: n.to_array(),
+            normal: n.to_array(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshKind {
+    /// Latitude/longitude sphere. Cheap to build, but triangles bunch up at the poles.
+    UvSphere,
+    /// Subdivided icosahedron with near-uniform triangle sizes.
+    Icosphere,
+}
+
+/// CPU-side geometry of a unit sphere centred on the origin.
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    /// `segments` around the equator, `rings` from pole to pole.
+    pub fn uv_sphere(segments: u32, rings: u32) -> Self {
+        let segments = segments.max(3);
+        let rings = rings.max(2);
+
+        let mut vertices = Vec::with_capacity(((segments + 1) * (rings + 1)) as usize);
+        for ring in 0..=rings {
+            let theta = std::f32::consts::PI * ring as f32 / rings as f32;
+            for segment in 0..=segments {
+                let phi = std::f32::consts::TAU * segment as f32 / segments as f32;
+                vertices.push(Vertex::on_unit_sphere(Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                )));
+            }
+        }
+
+        let mut indices = Vec::with_capacity((segments * rings * 6) as usize);
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let a = ring * (segments + 1) + segment;
+                let b = a + segments + 1;
+                // The first and last rings collapse into the poles; skip the
+                // degenerate half of each quad there.
+                if ring != 0 {
+                    indices.extend_from_slice(&[a, a + 1, b]);
+                }
+                if ring != rings - 1 {
+                    indices.extend_from_slice(&[a + 1, b + 1, b]);
+                }
+            }
+        }
+
+        Self { vertices, indices }
+    }
+
+    /// Icosahedron with every face split into four `subdivisions` times.
+    pub fn icosphere(subdivisions: u32) -> Self {
+        let t = (1.0 + 5.0_f32.sqrt()) / 2.0;
+        let corners = [
+            [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+            [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+            [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+        ];
+
+        let mut vertices: Vec<Vertex> = corners
+            .iter()
+            .map(|&c| Vertex::on_unit_sphere(Vec3::from_array(c)))
+            .collect();
+
+        let mut indices: Vec<u32> = vec![
+            0, 11, 5, 0, 5, 1, 0, 1, 7, 0, 7, 10, 0, 10, 11,
+            1, 5, 9, 5, 11, 4, 11, 10, 2, 10, 7, 6, 7, 1, 8,
+            3, 9, 4, 3, 4, 2, 3, 2, 6, 3, 6, 8, 3, 8, 9,
+            4, 9, 5, 2, 4, 11, 6, 2, 10, 8, 6, 7, 9, 8, 1,
+        ];
+
+        for _ in 0..subdivisions {
+            // Shared edges must reuse the same midpoint vertex
+            let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+            let mut midpoint = |a: u32, b: u32, vertices: &mut Vec<Vertex>| -> u32 {
+                let key = (a.min(b), a.max(b));
+                *midpoints.entry(key).or_insert_with(|| {
+                    let pa = Vec3::from_array(vertices[a as usize].position);
+                    let pb = Vec3::from_array(vertices[b as usize].position);
+                    vertices.push(Vertex::on_unit_sphere(pa + pb));
+                    vertices.len() as u32 - 1
+                })
+            };
+
+            let mut subdivided = Vec::with_capacity(indices.len() * 4);
+            for face in indices.chunks_exact(3) {
+                let (a, b, c) = (face[0], face[1], face[2]);
+                let ab = midpoint(a, b, &mut vertices);
+                let bc = midpoint(b, c, &mut vertices);
+                let ca = midpoint(c, a, &mut vertices);
+                subdivided.extend_from_slice(&[a, ab, ca, b, bc, ab, c, ca, bc, ab, bc, ca]);
+            }
+            indices = subdivided;
+        }
+
+        Self { vertices, indices }
+    }
+}
+
+/// Where one tessellation level lives inside the shared vertex/index buffers.
+#[derive(Clone, Copy, Debug)]
+pub struct MeshLevel {
+    pub base_vertex: i32,
+    pub first_index: u32,
+    pub index_count: u32,
+}
+
+/// Every tessellation level of one sphere kind, uploaded into a single pair
+/// of GPU buffers so that draws only differ by their index range.
+pub struct SphereMesh {
+    pub kind: MeshKind,
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub levels: Vec<MeshLevel>,
+}
+
+impl SphereMesh {
+    /// Number of tessellation levels generated, from coarsest to finest.
+    pub const LEVEL_COUNT: usize = 4;
+
+    pub fn level_data(kind: MeshKind, level: usize) -> MeshData {
+        match kind {
+            MeshKind::UvSphere => {
+                let segments = 8 << level;
+                MeshData::uv_sphere(segments, segments / 2)
+            }
+            MeshKind::Icosphere => MeshData::icosphere(level as u32),
+        }
+    }
+
+    pub fn new(device: &wgpu::Device, kind: MeshKind) -> Self {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut levels = Vec::with_capacity(Self::LEVEL_COUNT);
+
+        for level in 0..Self::LEVEL_COUNT {
+            let data = Self::level_data(kind, level);
+            levels.push(MeshLevel {
+                base_vertex: vertices.len() as i32,
+                first_index: indices.len() as u32,
+                index_count: data.indices.len() as u32,
+            });
+            vertices.extend_from_slice(&data.vertices);
+            indices.extend_from_slice(&data.indices);
+        }
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sphere Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sphere Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            kind,
+            vertex_buffer,
+            index_buffer,
+            levels,
+        }
+    }
+
+    pub fn level(&self, level: usize) -> MeshLevel {
+        self.levels[level.min(self.levels.len() - 1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_unit_sphere(data: &MeshData) {
+        assert_eq!(data.indices.len() % 3, 0);
+        assert!(data.indices.iter().all(|&index| (index as usize) < data.vertices.len()));
+
+        for vertex in &data.vertices {
+            let position = Vec3::from_array(vertex.position);
+            assert!((position.length() - 1.0).abs() < 1e-5, "{position} is off the unit sphere");
+            assert_eq!(vertex.position, vertex.normal);
+        }
+
+        // Counter-clockwise seen from outside: the face normal points away
+        // from the centre. Triangles collapsed at a pole are never emitted.
+        for face in data.indices.chunks_exact(3) {
+            let [a, b, c] = [face[0], face[1], face[2]].map(|index| Vec3::from_array(data.vertices[index as usize].position));
+            let normal = (b - a).cross(c - a);
+            assert!(normal.length() > 1e-7, "degenerate triangle {face:?}");
+            assert!(normal.dot(a + b + c) > 0.0, "triangle {face:?} faces inward");
+        }
+    }
+
+    #[test]
+    fn uv_sphere() {
+        for (segments, rings) in [(3, 2), (8, 4), (16, 9), (64, 32)] {
+            let data = MeshData::uv_sphere(segments, rings);
+            check_unit_sphere(&data);
+            assert_eq!(data.indices.len() as u32 / 3, 2 * segments * (rings - 1));
+        }
+    }
+
+    #[test]
+    fn uv_sphere_clamps_its_resolution() {
+        assert_eq!(MeshData::uv_sphere(0, 0).indices, MeshData::uv_sphere(3, 2).indices);
+    }
+
+    #[test]
+    fn icosphere() {
+        for subdivisions in 0..4 {
+            let data = MeshData::icosphere(subdivisions);
+            check_unit_sphere(&data);
+            assert_eq!(data.indices.len() / 3, 20 * 4usize.pow(subdivisions));
+            // Closed, with every shared edge reusing its midpoint: V - E + F = 2
+            assert_eq!(data.vertices.len(), 10 * 4usize.pow(subdivisions) + 2);
+        }
+    }
+
+    #[test]
+    fn levels_get_finer() {
+        for kind in [MeshKind::UvSphere, MeshKind::Icosphere] {
+            let triangles: Vec<usize> = (0..SphereMesh::LEVEL_COUNT)
+                .map(|level| {
+                    let data = SphereMesh::level_data(kind, level);
+                    check_unit_sphere(&data);
+                    data.indices.len() / 3
+                })
+                .collect();
+            assert!(triangles.windows(2).all(|pair| pair[0] < pair[1]), "{kind:?}: {triangles:?}");
+        }
+    }
+}
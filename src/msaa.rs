@@ -0,0 +1,235 @@
+use std::fmt;
+
+use crate::tonemap::HDR_FORMAT;
+
+/// Sample counts worth offering; wgpu accepts no others.
+const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+/// Returned when the scene targets can't be multisampled `requested` times.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsupportedSampleCount {
+    pub requested: u32,
+    pub supported: Vec<u32>,
+}
+
+impl fmt::Display for UnsupportedSampleCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x MSAA is not supported, use one of {:?}", self.requested, self.supported)
+    }
+}
+
+impl std::error::Error for UnsupportedSampleCount {}
+
+/// Sample counts that both the HDR color and the depth format support. Counts
+/// beyond the guaranteed ones need `TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES`
+/// on the device, not just the adapter.
+pub(crate) fn supported_sample_counts(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    depth_format: wgpu::TextureFormat,
+) -> Vec<u32> {
+    // The GL backend allocates multisampled textures but binds them as plain
+    // 2D textures, so the depth resolve would read zeros
+    if adapter.get_info().backend == wgpu::Backend::Gl {
+        return vec![1];
+    }
+
+    let adapter_specific = device.features().contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    let format_features = |format: wgpu::TextureFormat| {
+        if adapter_specific {
+            adapter.get_texture_format_features(format)
+        } else {
+            format.guaranteed_format_features(device.features())
+        }
+    };
+    let color = format_features(HDR_FORMAT);
+    let depth = format_features(depth_format);
+    SAMPLE_COUNTS
+        .into_iter()
+        .filter(|&count| {
+            count == 1
+                || (color.flags.sample_count_supported(count)
+                    && color.flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+                    && depth.flags.sample_count_supported(count))
+        })
+        .collect()
+}
+
+/// Multisampled color and depth the sphere passes draw into. Color resolves
+/// into the HDR texture as part of each pass; depth needs its own pass.
+pub(crate) struct Multisampling {
+    sample_count: u32,
+    color_view: wgpu::TextureView,
+    depth_view: wgpu::TextureView,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    resolve_pipeline: wgpu::RenderPipeline,
+}
+
+impl Multisampling {
+    pub fn new(
+        device: &wgpu::Device,
+        sample_count: u32,
+        depth_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Depth Resolve Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/depth_resolve.wgsl").into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Depth Resolve Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: true,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth Resolve Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let resolve_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Depth Resolve Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_fullscreen"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_resolve"),
+                targets: &[],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let (color_view, depth_view) = create_targets(device, sample_count, depth_format, width, height);
+        let bind_group = create_bind_group(device, &bind_group_layout, &depth_view);
+
+        Self {
+            sample_count,
+            color_view,
+            depth_view,
+            bind_group_layout,
+            bind_group,
+            resolve_pipeline,
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn color_view(&self) -> &wgpu::TextureView {
+        &self.color_view
+    }
+
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth_view
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, depth_format: wgpu::TextureFormat, width: u32, height: u32) {
+        let (color_view, depth_view) = create_targets(device, self.sample_count, depth_format, width, height);
+        self.bind_group = create_bind_group(device, &self.bind_group_layout, &depth_view);
+        self.color_view = color_view;
+        self.depth_view = depth_view;
+    }
+
+    /// Writes the farthest sample of each pixel into `target`.
+    pub fn encode_depth_resolve(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Resolve Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: target,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_pipeline(&self.resolve_pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_targets(
+    device: &wgpu::Device,
+    sample_count: u32,
+    depth_format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+) -> (wgpu::TextureView, wgpu::TextureView) {
+    let create_view = |label: &str, format: wgpu::TextureFormat, usage: wgpu::TextureUsages| {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    };
+    let color_view = create_view("Multisampled Color Texture", HDR_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT);
+    let depth_view = create_view(
+        "Multisampled Depth Texture",
+        depth_format,
+        wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    );
+    (color_view, depth_view)
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    depth_view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Depth Resolve Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(depth_view),
+            },
+        ],
+    })
+}
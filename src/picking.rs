@@ -0,0 +1,312 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::mesh::Vertex;
+
+const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+/// Object-ID target the spheres of the last frame are redrawn into on
+/// request, with a depth buffer of its own so translucent spheres can be
+/// picked too.
+pub(crate) struct Picking {
+    mesh_pipeline: wgpu::RenderPipeline,
+    impostor_pipeline: wgpu::RenderPipeline,
+    depth_format: wgpu::TextureFormat,
+    id_texture: wgpu::Texture,
+    id_view: wgpu::TextureView,
+    depth_view: wgpu::TextureView,
+}
+
+impl Picking {
+    /// `sphere_layout` is the pipeline layout of the sphere passes, whose
+    /// bind groups the pick pass reuses, and `vertex_shader` the module with
+    /// their mesh vertex stage.
+    pub fn new(
+        device: &wgpu::Device,
+        sphere_layout: &wgpu::PipelineLayout,
+        vertex_shader: &wgpu::ShaderModule,
+        depth_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Picking Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/brdf.wgsl"),
+                    "\n",
+                    include_str!("shaders/fragment.wgsl"),
+                    "\n",
+                    include_str!("shaders/impostor.wgsl"),
+                    "\n",
+                    include_str!("shaders/picking.wgsl"),
+                )
+                .into(),
+            ),
+        });
+
+        let depth_stencil = wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        };
+        let id_target = wgpu::ColorTargetState {
+            format: ID_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        };
+        let mesh_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mesh Picking Pipeline"),
+            layout: Some(sphere_layout),
+            vertex: wgpu::VertexState {
+                module: vertex_shader,
+                entry_point: Some("vs_main"),
+                buffers: &[Vertex::layout()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_pick"),
+                targets: &[Some(id_target.clone())],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(depth_stencil.clone()),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let impostor_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Impostor Picking Pipeline"),
+            layout: Some(sphere_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_impostor"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_impostor_pick"),
+                targets: &[Some(id_target)],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(depth_stencil),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let (id_texture, id_view, depth_view) = create_targets(device, depth_format, width, height);
+
+        Self {
+            mesh_pipeline,
+            impostor_pipeline,
+            depth_format,
+            id_texture,
+            id_view,
+            depth_view,
+        }
+    }
+
+    pub fn mesh_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.mesh_pipeline
+    }
+
+    pub fn impostor_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.impostor_pipeline
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.id_texture, self.id_view, self.depth_view) = create_targets(device, self.depth_format, width, height);
+    }
+
+    /// Starts the pick pass with cleared targets, rasterizing only inside
+    /// the `width` x `height` box at (`x`, `y`).
+    pub fn begin_pass<'a>(
+        &self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        (x, y): (u32, u32),
+        (width, height): (u32, u32),
+    ) -> wgpu::RenderPass<'a> {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Picking Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.id_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_scissor_rect(x, y, width, height);
+        pass
+    }
+
+    /// Copies the IDs in the box the pick pass drew into a new buffer.
+    pub fn encode_readback(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        (x, y): (u32, u32),
+        (width, height): (u32, u32),
+    ) -> PickReadback {
+        let bytes_per_row = (width * std::mem::size_of::<u32>() as u32).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pick Readback Buffer"),
+            size: bytes_per_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.id_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        PickReadback {
+            buffer,
+            width,
+            bytes_per_row,
+        }
+    }
+}
+
+/// IDs copied out of the pick target by [`Picking::encode_readback`].
+pub(crate) struct PickReadback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    bytes_per_row: u32,
+}
+
+impl PickReadback {
+    /// Waits for `submission`, the one that made the copy, and returns the
+    /// IDs row by row, zero where no sphere was drawn. Empty if the buffer
+    /// couldn't be mapped.
+    pub async fn read(self, device: &wgpu::Device, submission: wgpu::SubmissionIndex) -> Vec<u32> {
+        let state = Arc::new(Mutex::new(MapState::default()));
+        let callback_state = state.clone();
+        self.buffer.slice(..).map_async(wgpu::MapMode::Read, move |status| {
+            let mut state = callback_state.lock().unwrap();
+            state.mapped = Some(status.is_ok());
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        let mapped = MapFuture {
+            device,
+            submission: Some(submission),
+            state,
+        };
+        if !mapped.await {
+            return Vec::new();
+        }
+
+        let data = self.buffer.slice(..).get_mapped_range();
+        data.chunks_exact(self.bytes_per_row as usize)
+            .flat_map(|row| bytemuck::cast_slice::<u8, u32>(&row[..self.width as usize * 4]).iter().copied())
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct MapState {
+    mapped: Option<bool>,
+    waker: Option<Waker>,
+}
+
+/// Resolves once a `map_async` callback has run. The callback only runs
+/// when the device is polled, so the first poll blocks until the copy's
+/// submission is done; should the callback still be pending after that, it
+/// wakes the future itself. Any executor completes it, `pollster` included.
+struct MapFuture<'a> {
+    device: &'a wgpu::Device,
+    submission: Option<wgpu::SubmissionIndex>,
+    state: Arc<Mutex<MapState>>,
+}
+
+impl Future for MapFuture<'_> {
+    type Output = bool;
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<bool> {
+        self.state.lock().unwrap().waker = Some(context.waker().clone());
+        if let Some(submission) = self.submission.take() {
+            self.device.poll(wgpu::Maintain::wait_for(submission));
+        }
+        match self.state.lock().unwrap().mapped {
+            Some(mapped) => Poll::Ready(mapped),
+            None => Poll::Pending,
+        }
+    }
+}
+
+fn create_targets(
+    device: &wgpu::Device,
+    depth_format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+) -> (wgpu::Texture, wgpu::TextureView, wgpu::TextureView) {
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let id_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Pick ID Texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: ID_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let id_view = id_texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let depth_view = device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("Pick Depth Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: depth_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default());
+    (id_texture, id_view, depth_view)
+}
@@ -0,0 +1,555 @@
+use std::any::Any;
+use std::fmt;
+
+use glam::Mat4;
+
+use crate::bloom::Bloom;
+use crate::selection::SelectionOutline;
+use crate::tonemap::{ToneMapper, HDR_FORMAT};
+
+const SRGB_SOURCE: &str = include_str!("shaders/srgb.wgsl");
+const PRELUDE_SOURCE: &str = include_str!("shaders/post.wgsl");
+
+/// Handle to a step of the post-processing chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PostEffectId(pub(crate) u32);
+
+impl PostEffectId {
+    /// The built-in bloom, configured through
+    /// [`SphereRenderer::set_bloom_settings`](crate::renderer::SphereRenderer::set_bloom_settings).
+    pub const BLOOM: Self = Self(0);
+    /// The built-in tone mapping, configured through
+    /// [`SphereRenderer::set_tone_mapping`](crate::renderer::SphereRenderer::set_tone_mapping).
+    pub const TONE_MAPPING: Self = Self(1);
+    /// The built-in outline around selected spheres, configured through
+    /// [`SphereRenderer::set_selection_settings`](crate::renderer::SphereRenderer::set_selection_settings).
+    /// Skipped while nothing is selected.
+    pub const SELECTION_OUTLINE: Self = Self(2);
+}
+
+/// Where a post effect writes its output.
+#[derive(Clone, Copy)]
+pub struct PostTarget<'a> {
+    pub view: &'a wgpu::TextureView,
+    pub format: wgpu::TextureFormat,
+    /// Set for the final target when its format doesn't encode sRGB on
+    /// write, in which case the effect has to encode its output itself.
+    pub encode_srgb: bool,
+}
+
+/// What a post effect gets to work with while encoding a frame.
+pub struct PostContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub encoder: &'a mut wgpu::CommandEncoder,
+    /// The single-sample scene depth.
+    pub depth_view: &'a wgpu::TextureView,
+    pub width: u32,
+    pub height: u32,
+    /// Unjittered camera matrices of the frame.
+    pub view: Mat4,
+    pub projection: Mat4,
+    /// Counts up by one every frame.
+    pub frame: u32,
+}
+
+/// One step of the post-processing chain the renderer runs on the finished
+/// scene. Steps run in chain order; each reads the previous step's output
+/// and all but the last write an intermediate [`Rgba16Float`] texture owned
+/// by the chain, so effects never have to manage ping-pong targets.
+///
+/// [`Rgba16Float`]: wgpu::TextureFormat::Rgba16Float
+pub trait PostEffect: Any {
+    /// Names the effect's passes in graphics debuggers.
+    fn label(&self) -> &str;
+
+    /// Disabled effects are skipped and cost nothing.
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    /// Called with the output size when the effect joins the chain and
+    /// whenever the render targets are resized.
+    fn resize(&mut self, _device: &wgpu::Device, _width: u32, _height: u32) {}
+
+    /// Reads `input`, which holds linear color, and writes every pixel of
+    /// `output`.
+    fn encode(&mut self, context: &mut PostContext, input: &wgpu::TextureView, output: &PostTarget);
+}
+
+/// Returned when the WGSL of a [`ShaderEffect`] doesn't compile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderEffectError {
+    pub label: String,
+    pub message: String,
+}
+
+impl fmt::Display for ShaderEffectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "post effect {:?} failed to build: {}", self.label, self.message)
+    }
+}
+
+impl std::error::Error for ShaderEffectError {}
+
+/// Mirrors `PostParams` in post.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    size: [f32; 2],
+    inverse_size: [f32; 2],
+    frame: u32,
+    encode_srgb: u32,
+    _padding: [u32; 2],
+    params: [[f32; 4]; 4],
+}
+
+/// A post effect written in WGSL. The source is appended to the prelude in
+/// `shaders/post.wgsl`, which binds the input, a sampler, the scene depth
+/// and the effect's parameters, and must define
+/// `fn effect(in: PostInput) -> vec4<f32>`.
+pub struct ShaderEffect {
+    label: String,
+    enabled: bool,
+    params: [[f32; 4]; 4],
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipelines: FullscreenPipelines,
+}
+
+impl ShaderEffect {
+    /// Compiles `source` after the prelude. Compilation errors are returned
+    /// rather than raised on the device.
+    pub fn new(device: &wgpu::Device, label: &str, source: &str) -> Result<Self, ShaderEffectError> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(format!("{SRGB_SOURCE}\n{PRELUDE_SOURCE}\n{source}").into()),
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post Effect Params Buffer"),
+            size: std::mem::size_of::<PostUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Effect Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Post Effect Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let mut pipelines = FullscreenPipelines::new(device, label, shader, &[&bind_group_layout], "fs_effect");
+        // Build the intermediate format's pipeline now so that entry point
+        // and binding errors surface here too
+        pipelines.get(device, HDR_FORMAT);
+
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            return Err(ShaderEffectError {
+                label: label.to_owned(),
+                message: error.to_string(),
+            });
+        }
+
+        Ok(Self {
+            label: label.to_owned(),
+            enabled: true,
+            params: [[0.0; 4]; 4],
+            uniform_buffer,
+            sampler,
+            bind_group_layout,
+            pipelines,
+        })
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn params(&self) -> [[f32; 4]; 4] {
+        self.params
+    }
+
+    /// Values the shader reads as `post.params`.
+    pub fn set_params(&mut self, params: [[f32; 4]; 4]) {
+        self.params = params;
+    }
+}
+
+impl PostEffect for ShaderEffect {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn encode(&mut self, context: &mut PostContext, input: &wgpu::TextureView, output: &PostTarget) {
+        let uniform = PostUniform {
+            size: [context.width as f32, context.height as f32],
+            inverse_size: [1.0 / context.width as f32, 1.0 / context.height as f32],
+            frame: context.frame,
+            encode_srgb: output.encode_srgb as u32,
+            _padding: [0; 2],
+            params: self.params,
+        };
+        context.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Post Effect Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(input),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(context.depth_view),
+                },
+            ],
+        });
+
+        let pipeline = self.pipelines.get(context.device, output.format);
+        let mut pass = begin_fullscreen_pass(context.encoder, &self.label, output.view);
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+/// Starts a pass that overwrites all of `view`.
+pub(crate) fn begin_fullscreen_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    label: &str,
+    view: &wgpu::TextureView,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    })
+}
+
+/// The full-screen pipelines of one fragment entry point. A chain step
+/// writes either an intermediate texture or the final target, so pipelines
+/// are built on first use for each output format.
+pub(crate) struct FullscreenPipelines {
+    label: String,
+    shader: wgpu::ShaderModule,
+    layout: wgpu::PipelineLayout,
+    entry_point: &'static str,
+    pipelines: Vec<(wgpu::TextureFormat, wgpu::RenderPipeline)>,
+}
+
+impl FullscreenPipelines {
+    /// `shader` must have a `vs_fullscreen` entry point drawing one triangle.
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        shader: wgpu::ShaderModule,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        entry_point: &'static str,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        Self {
+            label: label.to_owned(),
+            shader,
+            layout,
+            entry_point,
+            pipelines: Vec::new(),
+        }
+    }
+
+    pub fn get(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) -> &wgpu::RenderPipeline {
+        let index = match self.pipelines.iter().position(|(built, _)| *built == format) {
+            Some(index) => index,
+            None => {
+                let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(&self.label),
+                    layout: Some(&self.layout),
+                    vertex: wgpu::VertexState {
+                        module: &self.shader,
+                        entry_point: Some("vs_fullscreen"),
+                        buffers: &[],
+                        compilation_options: Default::default(),
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &self.shader,
+                        entry_point: Some(self.entry_point),
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                        compilation_options: Default::default(),
+                    }),
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                    multiview: None,
+                    cache: None,
+                });
+                self.pipelines.push((format, pipeline));
+                self.pipelines.len() - 1
+            }
+        };
+        &self.pipelines[index].1
+    }
+}
+
+/// The ordered post-processing steps: the built-in bloom, tone mapping and
+/// selection outline plus any added effects, and the two intermediate
+/// textures they ping-pong between.
+pub(crate) struct PostChain {
+    bloom: Bloom,
+    tone_mapper: ToneMapper,
+    selection_outline: SelectionOutline,
+    custom: Vec<(PostEffectId, Box<dyn PostEffect>)>,
+    order: Vec<PostEffectId>,
+    next_id: u32,
+    intermediate_views: [wgpu::TextureView; 2],
+    width: u32,
+    height: u32,
+}
+
+impl PostChain {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        Self {
+            bloom: Bloom::new(device, width, height),
+            tone_mapper: ToneMapper::new(device),
+            selection_outline: SelectionOutline::new(device, width, height),
+            custom: Vec::new(),
+            order: vec![PostEffectId::BLOOM, PostEffectId::TONE_MAPPING, PostEffectId::SELECTION_OUTLINE],
+            next_id: 3,
+            intermediate_views: create_intermediate_views(device, width, height),
+            width,
+            height,
+        }
+    }
+
+    pub fn bloom(&self) -> &Bloom {
+        &self.bloom
+    }
+
+    pub fn bloom_mut(&mut self) -> &mut Bloom {
+        &mut self.bloom
+    }
+
+    pub fn tone_mapper(&self) -> &ToneMapper {
+        &self.tone_mapper
+    }
+
+    pub fn tone_mapper_mut(&mut self) -> &mut ToneMapper {
+        &mut self.tone_mapper
+    }
+
+    pub fn selection_outline(&self) -> &SelectionOutline {
+        &self.selection_outline
+    }
+
+    pub fn selection_outline_mut(&mut self) -> &mut SelectionOutline {
+        &mut self.selection_outline
+    }
+
+    pub fn order(&self) -> &[PostEffectId] {
+        &self.order
+    }
+
+    /// Adds `effect` at `index` in the chain, or at the end if `index` is
+    /// past it.
+    pub fn insert(&mut self, device: &wgpu::Device, index: usize, mut effect: Box<dyn PostEffect>) -> PostEffectId {
+        let id = PostEffectId(self.next_id);
+        self.next_id += 1;
+        effect.resize(device, self.width, self.height);
+        self.custom.push((id, effect));
+        self.order.insert(index.min(self.order.len()), id);
+        id
+    }
+
+    /// Takes an added effect out of the chain. The built-in steps can't be
+    /// removed, only disabled or moved.
+    pub fn remove(&mut self, id: PostEffectId) -> Option<Box<dyn PostEffect>> {
+        let index = self.custom.iter().position(|(effect_id, _)| *effect_id == id)?;
+        self.order.retain(|effect_id| *effect_id != id);
+        Some(self.custom.remove(index).1)
+    }
+
+    /// Moves a step to `index`, or to the end if `index` is past it.
+    pub fn move_to(&mut self, id: PostEffectId, index: usize) -> bool {
+        let Some(current) = self.order.iter().position(|effect_id| *effect_id == id) else {
+            return false;
+        };
+        self.order.remove(current);
+        self.order.insert(index.min(self.order.len()), id);
+        true
+    }
+
+    pub fn get_mut(&mut self, id: PostEffectId) -> Option<&mut dyn PostEffect> {
+        effect_mut(&mut self.bloom, &mut self.tone_mapper, &mut self.selection_outline, &mut self.custom, id)
+    }
+
+    pub fn is_enabled(&self, id: PostEffectId) -> bool {
+        match id {
+            PostEffectId::BLOOM => self.bloom.enabled(),
+            PostEffectId::TONE_MAPPING => self.tone_mapper.enabled(),
+            PostEffectId::SELECTION_OUTLINE => self.selection_outline.enabled(),
+            _ => self.custom.iter().any(|(effect_id, effect)| *effect_id == id && effect.enabled()),
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.intermediate_views = create_intermediate_views(device, width, height);
+        self.bloom.resize(device, width, height);
+        self.selection_outline.resize(device, width, height);
+        for (_, effect) in &mut self.custom {
+            effect.resize(device, width, height);
+        }
+    }
+
+    /// Runs the enabled steps in order from `scene` into `target`. Tone
+    /// mapping can't be disabled, so there is always at least one.
+    pub fn encode(&mut self, context: &mut PostContext, scene: &wgpu::TextureView, target: &PostTarget) {
+        let steps: Vec<PostEffectId> = self
+            .order
+            .iter()
+            .copied()
+            .filter(|&id| self.is_enabled(id))
+            .collect();
+        let mut input = scene;
+        for (index, &id) in steps.iter().enumerate() {
+            let output = if index + 1 == steps.len() {
+                *target
+            } else {
+                PostTarget {
+                    view: &self.intermediate_views[index % 2],
+                    format: HDR_FORMAT,
+                    encode_srgb: false,
+                }
+            };
+            let effect = effect_mut(
+                &mut self.bloom,
+                &mut self.tone_mapper,
+                &mut self.selection_outline,
+                &mut self.custom,
+                id,
+            );
+            if let Some(effect) = effect {
+                effect.encode(context, input, &output);
+            }
+            input = output.view;
+        }
+    }
+}
+
+// Takes the fields apart so the intermediate views stay borrowable
+fn effect_mut<'a>(
+    bloom: &'a mut Bloom,
+    tone_mapper: &'a mut ToneMapper,
+    selection_outline: &'a mut SelectionOutline,
+    custom: &'a mut [(PostEffectId, Box<dyn PostEffect>)],
+    id: PostEffectId,
+) -> Option<&'a mut dyn PostEffect> {
+    match id {
+        PostEffectId::BLOOM => Some(bloom),
+        PostEffectId::TONE_MAPPING => Some(tone_mapper),
+        PostEffectId::SELECTION_OUTLINE => Some(selection_outline),
+        _ => custom
+            .iter_mut()
+            .find(|(effect_id, _)| *effect_id == id)
+            .map(|(_, effect)| effect.as_mut()),
+    }
+}
+
+fn create_intermediate_views(device: &wgpu::Device, width: u32, height: u32) -> [wgpu::TextureView; 2] {
+    [0, 1].map(|_| {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Post Intermediate Texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    })
+}
@@ -1,8 +1,60 @@
-use glam::{Vec3, Vec4, Mat4};
+use glam::{Vec2, Vec3, Mat4};
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+use crate::ambient_occlusion::{AmbientOcclusionSettings, SphereOcclusion};
+use crate::bloom::BloomSettings;
+use crate::cluster::LightClusters;
+use crate::environment::{EnvironmentLighting, EnvironmentMap};
+use crate::debug::{DebugView, DebugViews};
+use crate::culling::{CullState, CullStats, CullUniform, Frustum, LodThresholds, IMPOSTOR_BUCKET, LOD_BUCKET_COUNT};
+use crate::hiz::DepthPyramid;
+use crate::light::{GpuLight, Light, LightHeader, LightId};
+use crate::shadow::{PointShadow, ShadowMaps, ShadowSettings, ShadowView, MAX_POINT_SHADOWS, MAX_SHADOW_VIEWS};
+use crate::ssao::{Ssao, SsaoSettings};
+use crate::taa::{Taa, TaaSettings};
+use crate::tonemap::{ToneMapping, HDR_FORMAT};
+use crate::transparency::Transparency;
+use crate::mesh::{MeshKind, SphereMesh, Vertex};
+use crate::msaa::{Multisampling, UnsupportedSampleCount};
+use crate::picking::Picking;
+use crate::post::{PostChain, PostContext, PostEffect, PostEffectId, PostTarget};
+use crate::selection::{Selection, SelectionSettings};
+
+/// Capacity of the sphere and visibility buffers.
+pub const MAX_SPHERES: usize = 1_000_000;
+
+// Indirect draws per frame: every LOD bucket, for the early, late and
+// transparent draw lists
+const DRAW_COMMAND_COUNT: usize = DrawList::COUNT * LOD_BUCKET_COUNT;
+
+// Culling runs once for the camera and once per shadow view
+const MAX_CULL_VIEWS: usize = 1 + MAX_SHADOW_VIEWS;
+
+/// The lists culling sorts visible spheres into, in buffer order. Must
+/// match the draw_commands layout in compute.wgsl.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DrawList {
+    /// Opaque spheres that passed against last frame's depth pyramid.
+    Early,
+    /// Opaque spheres that only passed against this frame's.
+    Late,
+    /// Translucent spheres from either pass.
+    Transparent,
+}
+
+impl DrawList {
+    const COUNT: usize = 3;
+}
+
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Sphere {
     pub position: [f32; 3],
     pub radius: f32,
@@ -11,12 +63,33 @@ pub struct Sphere {
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Material {
     pub base_color: [f32; 4],
     pub metallic_roughness: [f32; 2],
     pub emission: [f32; 3],
-    pub _padding: f32,
+    pub _padding: [f32; 3], // Pad to 48 bytes, the WGSL array stride
+}
+
+impl Material {
+    /// Spheres with an alpha below one are drawn in the transparent pass,
+    /// which blends them in any order and leaves them out of the shadow
+    /// maps and depth-based effects.
+    pub fn is_translucent(&self) -> bool {
+        self.base_color[3] < 1.0
+    }
+}
+
+impl Default for Material {
+    /// White, non-metallic, medium roughness.
+    fn default() -> Self {
+        Self {
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            metallic_roughness: [0.0, 0.5],
+            emission: [0.0; 3],
+            _padding: [0.0; 3],
+        }
+    }
 }
 
 #[repr(C)]
@@ -24,6 +97,9 @@ pub struct Material {
 struct CameraUniform {
     view_proj: [[f32; 4]; 4],
     position: [f32; 4],
+    previous_view_proj: [[f32; 4]; 4],
+    jitter: [f32; 4],
+    clip_planes: [f32; 4],
 }
 
 pub struct Camera {
@@ -84,18 +160,133 @@ impl Camera {
     }
 }
 
+/// Rejected material or sphere uploads. Nothing is written to the GPU when
+/// one of these is returned.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MaterialError {
+    /// Spheres, by position in the uploaded slice, whose `material_index`
+    /// is not below `material_count`.
+    SphereIndexOutOfRange { spheres: Vec<usize>, material_count: u32 },
+    /// A single material slot past the end of the uploaded materials.
+    SlotOutOfRange { index: u32, material_count: u32 },
+    /// The new material list is too short for the spheres already uploaded.
+    InUse { required: u32, material_count: u32 },
+}
+
+impl fmt::Display for MaterialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SphereIndexOutOfRange { spheres, material_count } => {
+                write!(f, "{} sphere(s) reference materials outside 0..{material_count}", spheres.len())?;
+                match spheres.first() {
+                    Some(first) => write!(f, ", first at sphere {first}"),
+                    None => Ok(()),
+                }
+            }
+            Self::SlotOutOfRange { index, material_count } => {
+                write!(f, "material slot {index} is outside 0..{material_count}")
+            }
+            Self::InUse { required, material_count } => write!(
+                f,
+                "current spheres need {required} materials but only {material_count} were given",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MaterialError {}
+
+/// How spheres are rasterized. Both modes share the depth buffer, so their
+/// output composites correctly with each other and with other geometry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Instanced tessellated meshes from [`SphereMesh`].
+    Mesh,
+    /// One camera-facing quad per sphere, ray-cast per pixel for exact
+    /// silhouettes and depth.
+    Impostor,
+}
+
 pub struct SphereRenderer {
     pub device: Arc<wgpu::Device>,
     pub queue: Arc<wgpu::Queue>,
     render_pipeline: wgpu::RenderPipeline,
+    impostor_pipeline: wgpu::RenderPipeline,
+    // Kept to rebuild the two pipelines above when the sample count changes
+    render_pipeline_layout: wgpu::PipelineLayout,
+    vertex_shader: wgpu::ShaderModule,
+    fragment_shader: wgpu::ShaderModule,
+    impostor_shader: wgpu::ShaderModule,
     compute_pipeline: wgpu::ComputePipeline,
+    late_compute_pipeline: wgpu::ComputePipeline,
     sphere_buffer: wgpu::Buffer,
+    previous_sphere_buffer: wgpu::Buffer,
+    // The previous sphere buffer holds last frame's spheres, which differ
+    // from the current ones
+    spheres_moved: bool,
     material_buffer: wgpu::Buffer,
+    material_count: u32,
+    visible_buffer: wgpu::Buffer,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
+    sphere_bind_group_layout: wgpu::BindGroupLayout,
     sphere_bind_group: wgpu::BindGroup,
+    visible_stride: u64,
     light_buffer: wgpu::Buffer,
+    light_clusters: LightClusters,
+    lights: Vec<(LightId, Light)>,
+    next_light_id: u32,
+    shadow_casters: HashSet<LightId>,
+    environment: EnvironmentLighting,
+    shadows: ShadowMaps,
+    ambient_occlusion: SphereOcclusion,
+    ssao: Ssao,
+    taa: Taa,
+    transparency: Transparency,
+    picking: Picking,
+    selection: Selection,
+    selection_settings: SelectionSettings,
+    debug_view: DebugView,
+    debug_views: DebugViews,
+    // Materials with an alpha below one; the transparent pass is skipped
+    // while there are none
+    translucent_materials: HashSet<u32>,
+    // Unjittered, for reprojecting into last frame
+    previous_view_proj: Option<Mat4>,
+    post: PostChain,
+    // Format of the targets given to `render`
+    target_format: wgpu::TextureFormat,
+    frame: u32,
     compute_bind_group: wgpu::BindGroup,
+    hiz_bind_group_layout: wgpu::BindGroupLayout,
+    hiz_bind_group: wgpu::BindGroup,
+    cull_material_bind_group_layout: wgpu::BindGroupLayout,
+    cull_material_bind_group: wgpu::BindGroup,
+    depth_pyramid: DepthPyramid,
+    occlusion_culling: bool,
+    cull_buffer: wgpu::Buffer,
+    cull_stride: u64,
+    draw_args_template: wgpu::Buffer,
+    cull_state_template: wgpu::Buffer,
+    cull_state_buffer: wgpu::Buffer,
+    indirect_buffer: wgpu::Buffer,
+    stats_buffer: wgpu::Buffer,
+    stats_ready: Arc<AtomicBool>,
+    stats_in_flight: bool,
+    stats_total: u32,
+    cull_stats: CullStats,
+    mesh: SphereMesh,
+    lod_thresholds: LodThresholds,
+    mode: RenderMode,
+    depth_view: wgpu::TextureView,
+    hdr_view: wgpu::TextureView,
+    // Multisampled targets, absent at one sample per pixel
+    msaa: Option<Multisampling>,
+    width: u32,
+    height: u32,
+    sphere_count: u32,
+    // One past the highest material index used by the current spheres
+    materials_required: u32,
 }
 
 impl SphereRenderer {
@@ -103,6 +294,7 @@ impl SphereRenderer {
         device: Arc<wgpu::Device>,
         queue: Arc<wgpu::Queue>,
         config: &wgpu::SurfaceConfiguration,
+        mode: RenderMode,
     ) -> Self {
         // Create shader modules
         let compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
@@ -117,7 +309,24 @@ impl SphereRenderer {
 
         let fragment_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Fragment Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/fragment.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(include_str!("shaders/brdf.wgsl"), "\n", include_str!("shaders/fragment.wgsl")).into(),
+            ),
+        });
+
+        // The impostor shader reuses the shading code from fragment.wgsl
+        let impostor_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Impostor Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/brdf.wgsl"),
+                    "\n",
+                    include_str!("shaders/fragment.wgsl"),
+                    "\n",
+                    include_str!("shaders/impostor.wgsl"),
+                )
+                .into(),
+            ),
         });
 
         // Create camera buffer and bind group
@@ -158,19 +367,95 @@ impl SphereRenderer {
         // Create empty buffers for spheres and materials
         let sphere_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Sphere Buffer"),
-            size: 1000000 * std::mem::size_of::<Sphere>() as u64,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            size: (MAX_SPHERES * std::mem::size_of::<Sphere>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
 
-        let material_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Material Buffer"),
-            size: 100 * std::mem::size_of::<Material>() as u64,
+        // The spheres as they were drawn last frame, copied over whenever
+        // they are replaced, for per-sphere motion in the velocity pass
+        let previous_sphere_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Previous Sphere Buffer"),
+            size: sphere_buffer.size(),
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
-        // Create bind group layout for spheres and light
+        // Culling output: compacted sphere indices and the indirect draw args,
+        // one region per LOD bucket for each draw list.
+        // Regions are selected with a dynamic offset, so keep them aligned.
+        let visible_stride = (MAX_SPHERES * std::mem::size_of::<u32>()) as u64;
+        let visible_stride = visible_stride.next_multiple_of(device.limits().min_storage_buffer_offset_alignment as u64);
+        let visible_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Sphere Buffer"),
+            size: DRAW_COMMAND_COUNT as u64 * visible_stride,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let recheck_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Recheck Buffer"),
+            size: (MAX_SPHERES * std::mem::size_of::<u32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let indirect_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect Draw Buffer"),
+            size: (DRAW_COMMAND_COUNT * std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>()) as u64,
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let cull_state_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull State Buffer"),
+            size: std::mem::size_of::<CullState>() as u64,
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        // Draw args followed by the cull state, copied back for `cull_stats`
+        let stats_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Stats Readback Buffer"),
+            size: indirect_buffer.size() + cull_state_buffer.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // One params slot per cull view, selected with a dynamic offset
+        let cull_stride = (std::mem::size_of::<CullUniform>() as u64)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64);
+        let cull_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Params Buffer"),
+            size: MAX_CULL_VIEWS as u64 * cull_stride,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Every cull view starts from these, copied in on the GPU since the
+        // views share the output buffers within a single submission
+        let draw_args_template = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect Draw Template Buffer"),
+            size: indirect_buffer.size(),
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let cull_state_template = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cull State Template Buffer"),
+            contents: bytemuck::bytes_of(&CullState::RESET),
+            usage: wgpu::BufferUsages::COPY_SRC,
+        });
+
+        // Start with a single default material so index 0 is always valid
+        let material_buffer = create_material_buffer(&device, &[Material::default()]);
+
+        // Create bind group layout for spheres, lights and materials
         let sphere_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Sphere Bind Group Layout"),
             entries: &[
@@ -186,9 +471,72 @@ impl SphereRenderer {
                 },
                 wgpu::BindGroupLayoutEntry {
                     binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: true,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Last frame's spheres, for the velocity pass
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Selection bits, for the tint and the outline mask
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Light lists per cluster
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
@@ -201,9 +549,65 @@ impl SphereRenderer {
         let compute_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Compute Bind Group Layout"),
             entries: &[
+                // spheres
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // draw_command
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // visible_spheres
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // params
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // recheck_spheres
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // state
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Storage { read_only: false },
                         has_dynamic_offset: false,
@@ -214,28 +618,48 @@ impl SphereRenderer {
             ],
         });
 
-        // Create light buffer
-        let light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Light Buffer"),
-            size: std::mem::size_of::<[f32; 4]>() as u64,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
+        // The depth pyramid lives in its own group since it is rebuilt on resize
+        let hiz_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("HiZ Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
         });
 
-        let sphere_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Sphere Bind Group"),
-            layout: &sphere_bind_group_layout,
+        // Culling reads material alphas to route translucent spheres. Own
+        // group since the material buffer is reallocated as the list grows
+        let cull_material_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Cull Material Bind Group Layout"),
             entries: &[
-                wgpu::BindGroupEntry {
+                wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    resource: sphere_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: light_buffer.as_entire_binding(),
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
             ],
         });
+        let cull_material_bind_group =
+            create_cull_material_bind_group(&device, &cull_material_bind_group_layout, &material_buffer);
+
+        // No lights until some are added; IBL still lights the scene
+        let light_buffer = create_light_buffer(&device, &[]);
+        let light_clusters = LightClusters::new(&device, &light_buffer);
+
+        let ambient_occlusion = SphereOcclusion::new(&device, &sphere_buffer);
 
         // Create compute bind group
         let compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -246,105 +670,83 @@ impl SphereRenderer {
                     binding: 0,
                     resource: sphere_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: indirect_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: visible_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &cull_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<CullUniform>() as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: recheck_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: cull_state_buffer.as_entire_binding(),
+                },
             ],
         });
 
+        // Ambient light comes from the default sky until an HDR map is set
+        let environment = EnvironmentLighting::new(&device, &queue, &EnvironmentMap::default());
+
+        let shadows = ShadowMaps::new(&device, &sphere_bind_group_layout, ShadowSettings::default());
+
         // Create pipeline layouts
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[&camera_bind_group_layout, &sphere_bind_group_layout],
+            bind_group_layouts: &[
+                &camera_bind_group_layout,
+                &sphere_bind_group_layout,
+                environment.bind_group_layout(),
+                shadows.bind_group_layout(),
+            ],
             push_constant_ranges: &[],
         });
 
         let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Compute Pipeline Layout"),
-            bind_group_layouts: &[&compute_bind_group_layout, &sphere_bind_group_layout],
+            bind_group_layouts: &[&compute_bind_group_layout, &hiz_bind_group_layout, &cull_material_bind_group_layout],
             push_constant_ranges: &[],
         });
 
-        // Create render pipeline
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &vertex_shader,
-                entry_point: Some("vs_main"),
-                buffers: &[
-                    // Vertex buffer
-                    wgpu::VertexBufferLayout {
-                        array_stride: std::mem::size_of::<[f32; 6]>() as u64,
-                        step_mode: wgpu::VertexStepMode::Vertex,
-                        attributes: &[
-                            // position (first 3 floats)
-                            wgpu::VertexAttribute {
-                                format: wgpu::VertexFormat::Float32x3,
-                                offset: 0,
-                                shader_location: 0,
-                            },
-                            // normal (next 3 floats)
-                            wgpu::VertexAttribute {
-                                format: wgpu::VertexFormat::Float32x3,
-                                offset: 12,
-                                shader_location: 1,
-                            },
-                        ],
-                    },
-                    // Instance buffer
-                    wgpu::VertexBufferLayout {
-                        array_stride: std::mem::size_of::<[f32; 6]>() as u64,
-                        step_mode: wgpu::VertexStepMode::Instance,
-                        attributes: &[
-                            // instance_position
-                            wgpu::VertexAttribute {
-                                format: wgpu::VertexFormat::Float32x3,
-                                offset: 0,
-                                shader_location: 2,
-                            },
-                            // instance_material
-                            wgpu::VertexAttribute {
-                                format: wgpu::VertexFormat::Float32x3,
-                                offset: 12,
-                                shader_location: 3,
-                            },
-                        ],
-                    },
-                ],
-                compilation_options: Default::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &fragment_shader,
-                entry_point: Some("fs_main"),
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: Default::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+        // Owns the selection buffer, so it comes before the sphere bind group
+        let selection = Selection::new(&device, &render_pipeline_layout, DEPTH_FORMAT);
+        let sphere_bind_group = create_sphere_bind_group(
+            &device,
+            &sphere_bind_group_layout,
+            &SphereBindings {
+                spheres: &sphere_buffer,
+                lights: &light_buffer,
+                visible: &visible_buffer,
+                materials: &material_buffer,
+                occlusion: ambient_occlusion.occlusion_buffer(),
+                previous_spheres: &previous_sphere_buffer,
+                selection: selection.buffer(),
+                light_clusters: light_clusters.cluster_buffer(),
             },
-            multiview: None,
-            cache: None,
-        });
+        );
+
+        // Single-sampled until set_sample_count says otherwise
+        let (render_pipeline, impostor_pipeline) = create_sphere_pipelines(
+            &device,
+            &render_pipeline_layout,
+            &vertex_shader,
+            &fragment_shader,
+            &impostor_shader,
+            1,
+            SsaoSettings::default().enabled,
+        );
 
         // Create compute pipeline
         let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
@@ -356,39 +758,1530 @@ impl SphereRenderer {
             cache: None,
         });
 
+        let late_compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Late Compute Pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &compute_shader,
+            entry_point: Some("main_late"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        // Unit sphere geometry, instanced once per sphere
+        let mesh = SphereMesh::new(&device, MeshKind::Icosphere);
+
+        // Create depth texture matching the surface
+        let depth_view = create_depth_view(&device, config.width, config.height);
+        let depth_pyramid = DepthPyramid::new(&device, &depth_view, config.width, config.height);
+        let hiz_bind_group = create_hiz_bind_group(&device, &hiz_bind_group_layout, &depth_pyramid);
+        let ssao = Ssao::new(&device, HDR_FORMAT, &depth_view, config.width, config.height);
+
+        // Spheres are shaded in linear HDR and tone mapped into the surface format
+        let hdr_view = create_hdr_view(&device, config.width, config.height);
+        let taa = Taa::new(
+            &device,
+            &render_pipeline_layout,
+            DEPTH_FORMAT,
+            &hdr_view,
+            &depth_view,
+            config.width,
+            config.height,
+        );
+        let transparency = Transparency::new(
+            &device,
+            &render_pipeline_layout,
+            &vertex_shader,
+            DEPTH_FORMAT,
+            config.width,
+            config.height,
+        );
+        let picking = Picking::new(
+            &device,
+            &render_pipeline_layout,
+            &vertex_shader,
+            DEPTH_FORMAT,
+            config.width,
+            config.height,
+        );
+        let debug_views = DebugViews::new(
+            &device,
+            [&camera_bind_group_layout, &sphere_bind_group_layout, environment.bind_group_layout()],
+            &vertex_shader,
+            DEPTH_FORMAT,
+            (DRAW_COMMAND_COUNT, LOD_BUCKET_COUNT),
+            (config.width, config.height),
+        );
+        let post = PostChain::new(&device, config.width, config.height);
+
         Self {
             device,
             queue,
             render_pipeline,
+            impostor_pipeline,
+            render_pipeline_layout,
+            vertex_shader,
+            fragment_shader,
+            impostor_shader,
             compute_pipeline,
+            late_compute_pipeline,
             sphere_buffer,
+            previous_sphere_buffer,
+            spheres_moved: false,
             material_buffer,
+            material_count: 1,
+            visible_buffer,
             camera_buffer,
             camera_bind_group,
+            sphere_bind_group_layout,
             sphere_bind_group,
+            visible_stride,
             light_buffer,
+            light_clusters,
+            lights: Vec::new(),
+            next_light_id: 0,
+            shadow_casters: HashSet::new(),
+            environment,
+            shadows,
+            ambient_occlusion,
+            ssao,
+            taa,
+            transparency,
+            picking,
+            selection,
+            selection_settings: SelectionSettings::default(),
+            debug_view: DebugView::None,
+            debug_views,
+            translucent_materials: HashSet::new(),
+            previous_view_proj: None,
+            post,
+            target_format: config.format,
+            frame: 0,
             compute_bind_group,
+            hiz_bind_group_layout,
+            hiz_bind_group,
+            cull_material_bind_group_layout,
+            cull_material_bind_group,
+            depth_pyramid,
+            occlusion_culling: true,
+            cull_buffer,
+            cull_stride,
+            draw_args_template,
+            cull_state_template,
+            cull_state_buffer,
+            indirect_buffer,
+            stats_buffer,
+            stats_ready: Arc::new(AtomicBool::new(false)),
+            stats_in_flight: false,
+            stats_total: 0,
+            cull_stats: CullStats::default(),
+            mesh,
+            lod_thresholds: LodThresholds::default(),
+            mode,
+            depth_view,
+            hdr_view,
+            msaa: None,
+            width: config.width,
+            height: config.height,
+            sphere_count: 0,
+            materials_required: 0,
+        }
+    }
+
+    /// Rebuilds all size-dependent render targets. Zero-sized requests
+    /// (minimized windows) are ignored.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 || (width == self.width && height == self.height) {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.depth_view = create_depth_view(&self.device, width, height);
+        self.depth_pyramid.resize(&self.device, &self.depth_view, width, height);
+        self.ssao.resize(&self.device, &self.depth_view, width, height);
+        self.hdr_view = create_hdr_view(&self.device, width, height);
+        if let Some(msaa) = &mut self.msaa {
+            msaa.resize(&self.device, DEPTH_FORMAT, width, height);
+        }
+        self.taa.resize(&self.device, &self.hdr_view, &self.depth_view, width, height);
+        self.transparency.resize(&self.device, width, height);
+        self.picking.resize(&self.device, width, height);
+        self.debug_views.resize(&self.device, width, height);
+        self.post.resize(&self.device, width, height);
+        self.hiz_bind_group = create_hiz_bind_group(&self.device, &self.hiz_bind_group_layout, &self.depth_pyramid);
+    }
+
+    /// Adds a light to the scene and returns a handle for changing it later.
+    /// Lights are shaded per view cluster, and a cluster reached by more than
+    /// [`MAX_CLUSTER_LIGHTS`](crate::cluster::MAX_CLUSTER_LIGHTS) lights
+    /// ignores those added last.
+    pub fn add_light(&mut self, light: Light) -> LightId {
+        let id = LightId(self.next_light_id);
+        self.next_light_id += 1;
+        self.lights.push((id, light));
+        self.upload_lights();
+        id
+    }
+
+    /// Replaces a light, returning the previous one, or `None` if `id` was
+    /// already removed.
+    pub fn update_light(&mut self, id: LightId, light: Light) -> Option<Light> {
+        let (_, slot) = self.lights.iter_mut().find(|(light_id, _)| *light_id == id)?;
+        let previous = std::mem::replace(slot, light);
+        self.upload_lights();
+        Some(previous)
+    }
+
+    pub fn remove_light(&mut self, id: LightId) -> Option<Light> {
+        let index = self.lights.iter().position(|(light_id, _)| *light_id == id)?;
+        let (_, light) = self.lights.remove(index);
+        self.shadow_casters.remove(&id);
+        self.upload_lights();
+        Some(light)
+    }
+
+    pub fn light(&self, id: LightId) -> Option<&Light> {
+        self.lights.iter().find(|(light_id, _)| *light_id == id).map(|(_, light)| light)
+    }
+
+    pub fn lights(&self) -> impl Iterator<Item = (LightId, &Light)> {
+        self.lights.iter().map(|(id, light)| (*id, light))
+    }
+
+    /// Turns shadows on or off for a light. Only point lights render a
+    /// shadow map, the first [`MAX_POINT_SHADOWS`] with shadows on in the
+    /// order they were added; the sun's cascades are controlled by
+    /// [`ShadowSettings`]. Returns `false` if `id` was already removed.
+    pub fn set_light_shadows(&mut self, id: LightId, enabled: bool) -> bool {
+        if self.light(id).is_none() {
+            return false;
+        }
+        if enabled {
+            self.shadow_casters.insert(id);
+        } else {
+            self.shadow_casters.remove(&id);
+        }
+        self.upload_lights();
+        true
+    }
+
+    pub fn light_shadows(&self, id: LightId) -> bool {
+        self.shadow_casters.contains(&id)
+    }
+
+    /// Writes the packed light list, reallocating the buffer when it has
+    /// outgrown it.
+    fn upload_lights(&mut self) {
+        let mut lights: Vec<GpuLight> = self.lights.iter().map(|(_, light)| light.to_gpu()).collect();
+        if let Some(sun) = self.shadow_light() {
+            lights[sun].shadow = GpuLight::CASCADED_SHADOW;
+        }
+        for (cube, index) in self.point_shadow_lights().into_iter().enumerate() {
+            lights[index].shadow = cube as u32;
+        }
+        let size = (std::mem::size_of::<LightHeader>() + std::mem::size_of_val(lights.as_slice())) as u64;
+        if size > self.light_buffer.size() {
+            self.light_buffer = create_light_buffer(&self.device, &lights);
+            self.light_clusters.set_light_buffer(&self.device, &self.light_buffer);
+            self.sphere_bind_group = create_sphere_bind_group(
+                &self.device,
+                &self.sphere_bind_group_layout,
+                &self.sphere_bindings(),
+            );
+        } else {
+            let header = LightHeader {
+                count: lights.len() as u32,
+                _padding: [0; 3],
+            };
+            self.queue.write_buffer(&self.light_buffer, 0, bytemuck::bytes_of(&header));
+            if !lights.is_empty() {
+                self.queue.write_buffer(
+                    &self.light_buffer,
+                    std::mem::size_of::<LightHeader>() as u64,
+                    bytemuck::cast_slice(&lights),
+                );
+            }
+        }
+    }
+
+    /// Index of the light casting cascaded shadows: the first directional one.
+    fn shadow_light(&self) -> Option<usize> {
+        if !self.shadows.settings().enabled {
+            return None;
+        }
+        self.lights
+            .iter()
+            .position(|(_, light)| matches!(light, Light::Directional { .. }))
+    }
+
+    /// Indices of the point lights that get a cube map, in cube map order.
+    fn point_shadow_lights(&self) -> Vec<usize> {
+        if !self.shadows.settings().enabled {
+            return Vec::new();
+        }
+        self.lights
+            .iter()
+            .enumerate()
+            .filter(|(_, (id, light))| matches!(light, Light::Point { .. }) && self.shadow_casters.contains(id))
+            .map(|(index, _)| index)
+            .take(MAX_POINT_SHADOWS)
+            .collect()
+    }
+
+    pub fn shadow_settings(&self) -> ShadowSettings {
+        self.shadows.settings()
+    }
+
+    /// Configures the sun's cascades and the point light cube maps. The
+    /// cascade count is clamped to [`MAX_CASCADES`](crate::shadow::MAX_CASCADES).
+    pub fn set_shadow_settings(&mut self, settings: ShadowSettings) {
+        self.shadows.set_settings(&self.device, settings);
+        self.upload_lights();
+    }
+
+    pub fn ambient_occlusion_settings(&self) -> AmbientOcclusionSettings {
+        self.ambient_occlusion.settings()
+    }
+
+    /// Changes the sphere-to-sphere occlusion, recomputed on the next frame.
+    pub fn set_ambient_occlusion_settings(&mut self, settings: AmbientOcclusionSettings) {
+        self.ambient_occlusion.set_settings(settings);
+    }
+
+    pub fn ssao_settings(&self) -> SsaoSettings {
+        self.ssao.settings()
+    }
+
+    /// Switches the screen-space occlusion pass, which darkens the
+    /// image-based ambient light of the spheres once they are drawn.
+    pub fn set_ssao_settings(&mut self, settings: SsaoSettings) {
+        let toggled = settings.enabled != self.ssao.settings().enabled;
+        self.ssao.set_settings(&self.device, settings);
+        if toggled {
+            self.rebuild_sphere_pipelines();
         }
     }
 
-    pub fn update_sphere_data(&self, spheres: &[Sphere]) {
+    pub fn taa_settings(&self) -> TaaSettings {
+        self.taa.settings()
+    }
+
+    /// Switches temporal antialiasing. While it is on, the projection given
+    /// to [`render`](Self::render) is jittered by a different sub-pixel
+    /// offset each frame and the history starts over whenever it is
+    /// re-enabled or the targets are resized.
+    pub fn set_taa_settings(&mut self, settings: TaaSettings) {
+        self.taa.set_settings(settings);
+    }
+
+    pub fn bloom_settings(&self) -> BloomSettings {
+        self.post.bloom().settings()
+    }
+
+    /// Configures the glow around emissive and other bright surfaces.
+    pub fn set_bloom_settings(&mut self, settings: BloomSettings) {
+        self.post.bloom_mut().set_settings(settings);
+    }
+
+    pub fn tone_mapping(&self) -> ToneMapping {
+        self.post.tone_mapper().operator()
+    }
+
+    pub fn set_tone_mapping(&mut self, operator: ToneMapping) {
+        self.post.tone_mapper_mut().set_operator(operator);
+    }
+
+    /// Exposure compensation in EV; each step doubles or halves the scene
+    /// brightness before tone mapping.
+    pub fn exposure(&self) -> f32 {
+        self.post.tone_mapper().exposure()
+    }
+
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.post.tone_mapper_mut().set_exposure(exposure);
+    }
+
+    /// The post-processing chain in the order it runs, built-in steps
+    /// included.
+    pub fn post_effects(&self) -> &[PostEffectId] {
+        self.post.order()
+    }
+
+    /// Appends `effect` to the post-processing chain, after tone mapping
+    /// and the selection outline unless moved.
+    pub fn add_post_effect<E: PostEffect>(&mut self, effect: E) -> PostEffectId {
+        self.insert_post_effect(usize::MAX, effect)
+    }
+
+    /// Adds `effect` at `index` in the post-processing chain, or at the end
+    /// if `index` is past it.
+    pub fn insert_post_effect<E: PostEffect>(&mut self, index: usize, effect: E) -> PostEffectId {
+        self.post.insert(&self.device, index, Box::new(effect))
+    }
+
+    /// Takes an added effect out of the chain. Returns `None` for unknown
+    /// ids and for the built-in steps like [`PostEffectId::BLOOM`], which
+    /// can only be moved or disabled.
+    pub fn remove_post_effect(&mut self, id: PostEffectId) -> Option<Box<dyn PostEffect>> {
+        self.post.remove(id)
+    }
+
+    /// Moves a step to `index` in the chain, or to the end if `index` is
+    /// past it. Returns whether `id` is in the chain.
+    pub fn move_post_effect(&mut self, id: PostEffectId, index: usize) -> bool {
+        self.post.move_to(id, index)
+    }
+
+    /// The added effect behind `id`, if it is a `E`.
+    pub fn post_effect_mut<E: PostEffect>(&mut self, id: PostEffectId) -> Option<&mut E> {
+        let effect: &mut dyn std::any::Any = self.post.get_mut(id)?;
+        effect.downcast_mut()
+    }
+
+    /// Selects or deselects sphere `index`. Selection goes by position in
+    /// the sphere list and is kept across
+    /// [`update_sphere_data`](Self::update_sphere_data).
+    pub fn set_selected(&mut self, index: u32, selected: bool) {
+        self.selection.set(index..index.saturating_add(1), selected);
+    }
+
+    /// Selects or deselects every sphere in `indices`. Costs one bitset
+    /// word per 32 spheres, so whole ranges are cheap to select at once.
+    pub fn set_selected_range(&mut self, indices: Range<u32>, selected: bool) {
+        self.selection.set(indices, selected);
+    }
+
+    pub fn toggle_selected(&mut self, index: u32) {
+        self.selection.toggle(index..index.saturating_add(1));
+    }
+
+    pub fn toggle_selected_range(&mut self, indices: Range<u32>) {
+        self.selection.toggle(indices);
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection.clear();
+    }
+
+    pub fn is_selected(&self, index: u32) -> bool {
+        self.selection.contains(index)
+    }
+
+    pub fn selected_count(&self) -> usize {
+        self.selection.count()
+    }
+
+    pub fn selection_settings(&self) -> SelectionSettings {
+        self.selection_settings
+    }
+
+    pub fn set_selection_settings(&mut self, settings: SelectionSettings) {
+        self.selection_settings = settings;
+        self.selection.set_tint(&self.queue, settings.tint_color, settings.tint_strength);
+        self.post
+            .selection_outline_mut()
+            .set_outline(settings.outline_color, settings.outline_width);
+    }
+
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+
+    /// Switches what the following frames show, see [`DebugView`].
+    pub fn set_debug_view(&mut self, view: DebugView) {
+        if view == DebugView::None && self.debug_view != DebugView::None {
+            // The history is from before the debug frames
+            self.taa.reset_history();
+        }
+        self.debug_view = view;
+    }
+
+    /// Re-bakes the image-based lighting from `map`. Pass
+    /// `EnvironmentMap::default()` to go back to the built-in sky.
+    pub fn set_environment(&mut self, map: &EnvironmentMap) {
+        self.environment.set_map(&self.device, &self.queue, map);
+    }
+
+    pub fn occlusion_culling(&self) -> bool {
+        self.occlusion_culling
+    }
+
+    /// Toggles the two-phase depth pyramid test. Frustum culling always runs.
+    pub fn set_occlusion_culling(&mut self, enabled: bool) {
+        self.occlusion_culling = enabled;
+    }
+
+    pub fn cull_stats(&self) -> CullStats {
+        self.cull_stats
+    }
+
+    pub fn render_mode(&self) -> RenderMode {
+        self.mode
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        self.mode = mode;
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.msaa.as_ref().map_or(1, Multisampling::sample_count)
+    }
+
+    /// Sample counts [`set_sample_count`](Self::set_sample_count) accepts on
+    /// this device.
+    pub fn supported_sample_counts(&self, adapter: &wgpu::Adapter) -> Vec<u32> {
+        crate::msaa::supported_sample_counts(adapter, &self.device, DEPTH_FORMAT)
+    }
+
+    /// Switches the sphere passes to `sample_count` samples per pixel,
+    /// rebuilding their pipelines and targets. `adapter` must be the one the
+    /// device was created from.
+    pub fn set_sample_count(
+        &mut self,
+        adapter: &wgpu::Adapter,
+        sample_count: u32,
+    ) -> Result<(), UnsupportedSampleCount> {
+        if sample_count == self.sample_count() {
+            return Ok(());
+        }
+        let supported = self.supported_sample_counts(adapter);
+        if !supported.contains(&sample_count) {
+            return Err(UnsupportedSampleCount {
+                requested: sample_count,
+                supported,
+            });
+        }
+
+        self.msaa = (sample_count > 1)
+            .then(|| Multisampling::new(&self.device, sample_count, DEPTH_FORMAT, self.width, self.height));
+        self.ssao.set_sample_count(&self.device, sample_count);
+        self.rebuild_sphere_pipelines();
+        Ok(())
+    }
+
+    /// Recreates the sphere pipelines for the current sample count and
+    /// whether the sphere passes have an ambient target.
+    fn rebuild_sphere_pipelines(&mut self) {
+        (self.render_pipeline, self.impostor_pipeline) = create_sphere_pipelines(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.vertex_shader,
+            &self.fragment_shader,
+            &self.impostor_shader,
+            self.sample_count(),
+            self.ssao.settings().enabled,
+        );
+    }
+
+    /// Regenerates the sphere geometry for every detail level.
+    pub fn set_mesh_kind(&mut self, kind: MeshKind) {
+        if kind != self.mesh.kind {
+            self.mesh = SphereMesh::new(&self.device, kind);
+        }
+    }
+
+    pub fn lod_thresholds(&self) -> LodThresholds {
+        self.lod_thresholds
+    }
+
+    /// Sets the projected sizes at which spheres switch tessellation level.
+    /// They're sorted into ascending order first.
+    pub fn set_lod_thresholds(&mut self, thresholds: LodThresholds) {
+        self.lod_thresholds = thresholds.ascending();
+    }
+
+    /// Uploads the scene, replacing any previous spheres. At most
+    /// [`MAX_SPHERES`] are kept. Every `material_index` must refer to an
+    /// uploaded material, otherwise the previous scene is left in place.
+    pub fn update_sphere_data(&mut self, spheres: &[Sphere]) -> Result<(), MaterialError> {
+        let spheres = &spheres[..spheres.len().min(MAX_SPHERES)];
+
+        let out_of_range: Vec<usize> = spheres
+            .iter()
+            .enumerate()
+            .filter(|(_, sphere)| sphere.material_index >= self.material_count)
+            .map(|(index, _)| index)
+            .collect();
+        if !out_of_range.is_empty() {
+            return Err(MaterialError::SphereIndexOutOfRange {
+                spheres: out_of_range,
+                material_count: self.material_count,
+            });
+        }
+
+        // Same spheres in new places: keep the positions last drawn for the
+        // velocity pass, unless an earlier update since that frame already
+        // did. Otherwise there is nothing to reproject from.
+        let moved = spheres.len() as u32 == self.sphere_count;
+        if moved && !self.spheres_moved {
+            self.copy_spheres_to_previous(self.sphere_count);
+        }
         self.queue.write_buffer(&self.sphere_buffer, 0, bytemuck::cast_slice(spheres));
+        if !moved {
+            self.copy_spheres_to_previous(spheres.len() as u32);
+        }
+        self.spheres_moved = moved;
+
+        self.ambient_occlusion.set_spheres(&self.device, &self.sphere_buffer, spheres);
+        self.sphere_count = spheres.len() as u32;
+        self.materials_required = spheres.iter().map(|sphere| sphere.material_index + 1).max().unwrap_or(0);
+        Ok(())
+    }
+
+    /// Copies the first `count` spheres into the previous sphere buffer in a
+    /// submission of its own, ordered against the queue's pending writes.
+    fn copy_spheres_to_previous(&self, count: u32) {
+        if count == 0 {
+            return;
+        }
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Previous Spheres Encoder"),
+        });
+        let size = count as u64 * std::mem::size_of::<Sphere>() as u64;
+        encoder.copy_buffer_to_buffer(&self.sphere_buffer, 0, &self.previous_sphere_buffer, 0, size);
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    fn sphere_bindings(&self) -> SphereBindings<'_> {
+        SphereBindings {
+            spheres: &self.sphere_buffer,
+            lights: &self.light_buffer,
+            visible: &self.visible_buffer,
+            materials: &self.material_buffer,
+            occlusion: self.ambient_occlusion.occlusion_buffer(),
+            previous_spheres: &self.previous_sphere_buffer,
+            selection: self.selection.buffer(),
+            light_clusters: self.light_clusters.cluster_buffer(),
+        }
+    }
+
+    pub fn material_count(&self) -> u32 {
+        self.material_count
     }
 
-    pub fn render(&mut self, view: Mat4, projection: Mat4, light_pos: Vec3) {
+    /// Replaces the whole material list, growing the GPU buffer if needed.
+    /// The list may not shrink below what the current spheres reference.
+    pub fn update_material_data(&mut self, materials: &[Material]) -> Result<(), MaterialError> {
+        let material_count = materials.len() as u32;
+        if material_count < self.materials_required.max(1) {
+            return Err(MaterialError::InUse {
+                required: self.materials_required.max(1),
+                material_count,
+            });
+        }
+
+        let size = std::mem::size_of_val(materials) as u64;
+        if size > self.material_buffer.size() {
+            // Reallocate with headroom and rebind; the old buffer is freed
+            // once the bind group holding it is dropped.
+            let capacity = materials.len().next_power_of_two();
+            let mut contents = materials.to_vec();
+            contents.resize(capacity, Material::default());
+            self.material_buffer = create_material_buffer(&self.device, &contents);
+            self.sphere_bind_group = create_sphere_bind_group(
+                &self.device,
+                &self.sphere_bind_group_layout,
+                &self.sphere_bindings(),
+            );
+            self.cull_material_bind_group = create_cull_material_bind_group(
+                &self.device,
+                &self.cull_material_bind_group_layout,
+                &self.material_buffer,
+            );
+        } else {
+            self.queue.write_buffer(&self.material_buffer, 0, bytemuck::cast_slice(materials));
+        }
+        self.material_count = material_count;
+        self.translucent_materials = (0..material_count)
+            .filter(|&index| materials[index as usize].is_translucent())
+            .collect();
+        Ok(())
+    }
+
+    /// Overwrites one existing material slot.
+    pub fn set_material(&mut self, index: u32, material: Material) -> Result<(), MaterialError> {
+        if index >= self.material_count {
+            return Err(MaterialError::SlotOutOfRange {
+                index,
+                material_count: self.material_count,
+            });
+        }
+        let offset = index as u64 * std::mem::size_of::<Material>() as u64;
+        self.queue.write_buffer(&self.material_buffer, offset, bytemuck::bytes_of(&material));
+        if material.is_translucent() {
+            self.translucent_materials.insert(index);
+        } else {
+            self.translucent_materials.remove(&index);
+        }
+        Ok(())
+    }
+
+    pub fn render(&mut self, target: &wgpu::TextureView, view: Mat4, projection: Mat4, camera_position: Vec3) {
+        // Only rasterization sees the jitter; culling, shadows and
+        // reprojection work with the unjittered projection
+        let view_proj = projection * view;
+        let jitter = if self.debug_view == DebugView::None {
+            self.taa.jitter(self.width, self.height)
+        } else {
+            Vec2::ZERO
+        };
+        let jittered_projection = Mat4::from_translation(jitter.extend(0.0)) * projection;
+        let (near, far) = LightClusters::depth_range(clip_planes(projection));
+
         // Update camera buffer
         let camera_uniform = CameraUniform {
-            view_proj: (projection * view).to_cols_array_2d(),
-            position: [light_pos.x, light_pos.y, light_pos.z, 1.0],
+            view_proj: (jittered_projection * view).to_cols_array_2d(),
+            position: camera_position.extend(1.0).to_array(),
+            previous_view_proj: self.previous_view_proj.unwrap_or(view_proj).to_cols_array_2d(),
+            jitter: [jitter.x, jitter.y, 0.0, 0.0],
+            clip_planes: [near, far, 0.0, 0.0],
         };
         self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[camera_uniform]));
+        self.selection.upload(&self.queue);
+
+        self.poll_cull_stats();
+
+        let camera_cull = CullUniform {
+            occlusion_enabled: self.occlusion_culling as u32,
+            ..self.cull_uniform(view_proj, projection.y_axis.y * 0.5 * self.height as f32)
+        };
+        self.queue.write_buffer(&self.cull_buffer, 0, bytemuck::bytes_of(&camera_cull));
+
+        let light_direction = self.shadow_light().and_then(|index| match self.lights[index].1 {
+            Light::Directional { direction, .. } => Some(direction),
+            _ => None,
+        });
+        let point_lights: Vec<PointShadow> = self
+            .point_shadow_lights()
+            .into_iter()
+            .filter_map(|index| match self.lights[index].1 {
+                Light::Point { position, range, .. } => Some(PointShadow { position, range }),
+                _ => None,
+            })
+            .collect();
+        let shadow_views = self.shadows.update(&self.queue, view, projection, light_direction, &point_lights);
+        for (index, shadow_view) in shadow_views.iter().enumerate() {
+            let cull_uniform = self.cull_uniform(shadow_view.view_proj, shadow_view.lod_scale);
+            let offset = (index + 1) as u64 * self.cull_stride;
+            self.queue.write_buffer(&self.cull_buffer, offset, bytemuck::bytes_of(&cull_uniform));
+        }
+
+        // The culling passes fill in instance_count
+        let bucket_args: Vec<_> = (0..LOD_BUCKET_COUNT)
+            .map(|bucket| {
+                if bucket == IMPOSTOR_BUCKET {
+                    // Read back as DrawIndirectArgs { vertex_count: 6, instance_count, 0, 0 }
+                    wgpu::util::DrawIndexedIndirectArgs {
+                        index_count: 6,
+                        instance_count: 0,
+                        first_index: 0,
+                        base_vertex: 0,
+                        first_instance: 0,
+                    }
+                } else {
+                    let level = self.mesh.level(bucket);
+                    wgpu::util::DrawIndexedIndirectArgs {
+                        index_count: level.index_count,
+                        instance_count: 0,
+                        first_index: level.first_index,
+                        base_vertex: level.base_vertex,
+                        first_instance: 0,
+                    }
+                }
+            })
+            .collect();
+        let draw_args: Vec<u8> = (0..DrawList::COUNT)
+            .flat_map(|_| &bucket_args)
+            .flat_map(|args| args.as_bytes().to_vec())
+            .collect();
+        self.queue.write_buffer(&self.draw_args_template, 0, &draw_args);
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Encoder"),
+        });
+
+        self.ambient_occlusion.encode(&self.queue, &mut encoder);
+        self.light_clusters.encode(&self.queue, &mut encoder, (view, jittered_projection), (near, far));
+
+        // Shadow views go first so the camera's counters are the ones read back
+        for (index, shadow_view) in shadow_views.iter().enumerate() {
+            self.encode_early_cull(&mut encoder, index + 1);
+            let mut shadow_pass = self.shadows.begin_pass(&mut encoder, index, shadow_view);
+            self.draw_shadow_casters(&mut shadow_pass, shadow_view);
+        }
+
+        self.encode_early_cull(&mut encoder, 0);
+
+        self.encode_sphere_pass(&mut encoder, false);
+
+        if self.occlusion_culling {
+            // Rebuild the pyramid from what the early pass drew, then give the
+            // spheres it rejected a second chance against it
+            self.depth_pyramid.encode(&mut encoder);
+
+            {
+                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("Late Culling Pass"),
+                    timestamp_writes: None,
+                });
+                compute_pass.set_pipeline(&self.late_compute_pipeline);
+                compute_pass.set_bind_group(0, &self.compute_bind_group, &[0]);
+                compute_pass.set_bind_group(1, &self.hiz_bind_group, &[]);
+                compute_pass.set_bind_group(2, &self.cull_material_bind_group, &[]);
+                compute_pass.dispatch_workgroups_indirect(&self.cull_state_buffer, 0);
+            }
+
+            self.encode_sphere_pass(&mut encoder, true);
+
+            // Once more with the late spheres, so every occluder drawn this
+            // frame seeds next frame's early pass
+            self.depth_pyramid.encode(&mut encoder);
+        }
+
+        let post_target = PostTarget {
+            view: target,
+            format: self.target_format,
+            // sRGB targets encode on write; anything else gets it in the shader
+            encode_srgb: !self.target_format.is_srgb(),
+        };
+        if self.debug_view != DebugView::None {
+            self.encode_debug_view(&mut encoder, &post_target, (near, far));
+        } else {
+            if self.taa.enabled() {
+                self.encode_velocity_pass(&mut encoder);
+            }
 
-        // Update light buffer
-        let light_data = [light_pos.x, light_pos.y, light_pos.z, 1.0];
-        self.queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&light_data));
-        
-        // Rest of render implementation...
-        // Note: This is where you would create command encoder and render pass
-        // The actual rendering code would go here
+            self.ssao.encode(&self.queue, &mut encoder, &self.hdr_view, jittered_projection);
+            if !self.translucent_materials.is_empty() {
+                self.encode_transparent_pass(&mut encoder);
+            }
+            self.post.selection_outline_mut().set_active(self.selection.count() > 0);
+            if self.post.is_enabled(PostEffectId::SELECTION_OUTLINE) {
+                self.encode_selection_mask_pass(&mut encoder);
+            }
+            self.taa.encode(&self.queue, &mut encoder, &self.hdr_view);
+
+            let mut post_context = PostContext {
+                device: &self.device,
+                queue: &self.queue,
+                encoder: &mut encoder,
+                depth_view: &self.depth_view,
+                width: self.width,
+                height: self.height,
+                view,
+                projection,
+                frame: self.frame,
+            };
+            self.post.encode(&mut post_context, &self.hdr_view, &post_target);
+        }
+        self.frame = self.frame.wrapping_add(1);
+
+        let read_stats = !self.stats_in_flight;
+        if read_stats {
+            encoder.copy_buffer_to_buffer(&self.indirect_buffer, 0, &self.stats_buffer, 0, self.indirect_buffer.size());
+            encoder.copy_buffer_to_buffer(
+                &self.cull_state_buffer,
+                0,
+                &self.stats_buffer,
+                self.indirect_buffer.size(),
+                self.cull_state_buffer.size(),
+            );
+        }
+
+        // Whatever moved has now been drawn where it is
+        if self.spheres_moved {
+            let size = self.sphere_count as u64 * std::mem::size_of::<Sphere>() as u64;
+            encoder.copy_buffer_to_buffer(&self.sphere_buffer, 0, &self.previous_sphere_buffer, 0, size);
+            self.spheres_moved = false;
+        }
+        self.previous_view_proj = Some(view_proj);
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        if read_stats {
+            let ready = self.stats_ready.clone();
+            self.stats_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+                ready.store(result.is_ok(), Ordering::Release);
+            });
+            self.stats_in_flight = true;
+            self.stats_total = self.sphere_count;
+        }
+    }
+
+    /// Cull parameters for an arbitrary view, without occlusion culling.
+    fn cull_uniform(&self, view_proj: Mat4, lod_scale: f32) -> CullUniform {
+        let frustum = Frustum::from_view_projection(view_proj);
+        CullUniform {
+            view_proj: view_proj.to_cols_array_2d(),
+            planes: frustum.planes.map(|plane| plane.to_array()),
+            lod_thresholds: self.lod_thresholds.min_radius_px,
+            sphere_count: self.sphere_count,
+            occlusion_enabled: 0,
+            hiz_levels: self.depth_pyramid.level_count(),
+            lod_scale,
+            hiz_size: self.depth_pyramid.size(),
+            impostors_only: (self.mode == RenderMode::Impostor) as u32,
+            bucket_capacity: (self.visible_stride / std::mem::size_of::<u32>() as u64) as u32,
+        }
+    }
+
+    /// Resets the draw args and counters, then runs the early culling pass
+    /// with the params in slot `cull_view`.
+    fn encode_early_cull(&self, encoder: &mut wgpu::CommandEncoder, cull_view: usize) {
+        encoder.copy_buffer_to_buffer(
+            &self.draw_args_template,
+            0,
+            &self.indirect_buffer,
+            0,
+            self.indirect_buffer.size(),
+        );
+        encoder.copy_buffer_to_buffer(
+            &self.cull_state_template,
+            0,
+            &self.cull_state_buffer,
+            0,
+            self.cull_state_buffer.size(),
+        );
+
+        if self.sphere_count > 0 {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Early Culling Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.compute_pipeline);
+            compute_pass.set_bind_group(0, &self.compute_bind_group, &[(cull_view as u64 * self.cull_stride) as u32]);
+            compute_pass.set_bind_group(1, &self.hiz_bind_group, &[]);
+            compute_pass.set_bind_group(2, &self.cull_material_bind_group, &[]);
+            compute_pass.dispatch_workgroups(self.sphere_count.div_ceil(256), 1, 1);
+        }
+    }
+
+    /// Draws the early buckets of a shadow view as depth-only casters.
+    fn draw_shadow_casters(&self, render_pass: &mut wgpu::RenderPass, shadow_view: &ShadowView) {
+        self.draw_buckets(
+            render_pass,
+            DrawList::Early,
+            self.shadows.mesh_pipeline(shadow_view),
+            self.shadows.impostor_pipeline(shadow_view),
+        );
+    }
+
+    /// Issues the indirect draws of every LOD bucket of a draw list, meshes
+    /// with `mesh_pipeline` and billboards with `impostor_pipeline`.
+    fn draw_buckets(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        list: DrawList,
+        mesh_pipeline: &wgpu::RenderPipeline,
+        impostor_pipeline: &wgpu::RenderPipeline,
+    ) {
+        let first_command = list as usize * LOD_BUCKET_COUNT;
+        for command in first_command..first_command + LOD_BUCKET_COUNT {
+            self.draw_bucket(render_pass, command, mesh_pipeline, impostor_pipeline);
+        }
+    }
+
+    /// Issues the indirect draw of draw command `command`.
+    fn draw_bucket(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        command: usize,
+        mesh_pipeline: &wgpu::RenderPipeline,
+        impostor_pipeline: &wgpu::RenderPipeline,
+    ) {
+        let bucket = command % LOD_BUCKET_COUNT;
+        let command = command as u64;
+        let indirect_offset = command * std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as u64;
+        render_pass.set_bind_group(1, &self.sphere_bind_group, &[(command * self.visible_stride) as u32]);
+
+        if bucket == IMPOSTOR_BUCKET {
+            render_pass.set_pipeline(impostor_pipeline);
+            render_pass.draw_indirect(&self.indirect_buffer, indirect_offset);
+        } else if self.mode == RenderMode::Mesh {
+            render_pass.set_pipeline(mesh_pipeline);
+            render_pass.set_vertex_buffer(0, self.mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(self.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            render_pass.draw_indexed_indirect(&self.indirect_buffer, indirect_offset);
+        }
+    }
+
+    /// Draws the spheres listed by the early (`late == false`) or late culling
+    /// pass. Only the early pass clears the targets. Under MSAA both passes
+    /// resolve color as they end and depth right after.
+    fn encode_sphere_pass(&self, encoder: &mut wgpu::CommandEncoder, late: bool) {
+        let (color_load, depth_load) = if late {
+            (wgpu::LoadOp::Load, wgpu::LoadOp::Load)
+        } else {
+            (
+                wgpu::LoadOp::Clear(wgpu::Color {
+                    r: 0.1,
+                    g: 0.1,
+                    b: 0.1,
+                    a: 1.0,
+                }),
+                wgpu::LoadOp::Clear(1.0),
+            )
+        };
+
+        let (color_view, resolve_target, depth_view) = match &self.msaa {
+            Some(msaa) => (msaa.color_view(), Some(&self.hdr_view), msaa.depth_view()),
+            None => (&self.hdr_view, None, &self.depth_view),
+        };
+
+        let color_attachment = wgpu::RenderPassColorAttachment {
+            view: color_view,
+            resolve_target,
+            ops: wgpu::Operations {
+                load: color_load,
+                store: wgpu::StoreOp::Store,
+            },
+        };
+        // Where the pipelines write their ambient light while SSAO is on
+        let ambient_attachment = self.ssao.ambient_attachment().map(|(view, resolve_target)| {
+            wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: if late {
+                        wgpu::LoadOp::Load
+                    } else {
+                        wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)
+                    },
+                    store: wgpu::StoreOp::Store,
+                },
+            }
+        });
+        let color_attachments = [Some(color_attachment), ambient_attachment];
+        let color_attachment_count = if color_attachments[1].is_some() { 2 } else { 1 };
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Sphere Render Pass"),
+            color_attachments: &color_attachments[..color_attachment_count],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: depth_load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, self.environment.bind_group(), &[]);
+        render_pass.set_bind_group(3, self.shadows.bind_group(), &[]);
+
+        let list = if late { DrawList::Late } else { DrawList::Early };
+        self.draw_buckets(&mut render_pass, list, &self.render_pipeline, &self.impostor_pipeline);
+        drop(render_pass);
+
+        if let Some(msaa) = &self.msaa {
+            msaa.encode_depth_resolve(encoder, &self.depth_view);
+        }
+    }
+
+    /// Redraws the spheres of both culling passes into the velocity buffer,
+    /// tested against the finished single-sample depth.
+    fn encode_velocity_pass(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Velocity Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: self.taa.velocity_view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, self.environment.bind_group(), &[]);
+        render_pass.set_bind_group(3, self.shadows.bind_group(), &[]);
+        let (mesh_pipeline, impostor_pipeline) =
+            (self.taa.mesh_velocity_pipeline(), self.taa.impostor_velocity_pipeline());
+        self.draw_buckets(&mut render_pass, DrawList::Early, mesh_pipeline, impostor_pipeline);
+        if self.occlusion_culling {
+            self.draw_buckets(&mut render_pass, DrawList::Late, mesh_pipeline, impostor_pipeline);
+        }
+    }
+
+    /// Accumulates the translucent spheres both culling passes let through,
+    /// tested against the finished single-sample depth, and composites them
+    /// over the scene.
+    fn encode_transparent_pass(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = self.transparency.begin_pass(encoder, &self.depth_view);
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, self.environment.bind_group(), &[]);
+        render_pass.set_bind_group(3, self.shadows.bind_group(), &[]);
+        self.draw_buckets(
+            &mut render_pass,
+            DrawList::Transparent,
+            self.transparency.mesh_pipeline(),
+            self.transparency.impostor_pipeline(),
+        );
+        drop(render_pass);
+
+        self.transparency.encode_composite(encoder, &self.hdr_view);
+    }
+
+    /// Redraws every sphere culling let through with the debug view's
+    /// colors, replacing the rest of the frame, and copies the result to
+    /// `target`. `depth_range` is the camera's, with a finite far plane.
+    fn encode_debug_view(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &PostTarget,
+        depth_range: (f32, f32),
+    ) {
+        {
+            let mut render_pass = self.debug_views.begin_pass(
+                &self.queue,
+                encoder,
+                &self.hdr_view,
+                self.debug_view,
+                depth_range,
+            );
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(2, self.environment.bind_group(), &[]);
+            let (mesh_pipeline, impostor_pipeline) = self.debug_views.pipelines(self.debug_view);
+            for command in 0..DRAW_COMMAND_COUNT {
+                render_pass.set_bind_group(3, self.debug_views.bind_group(), &[self.debug_views.draw_info_offset(command)]);
+                self.draw_bucket(&mut render_pass, command, mesh_pipeline, impostor_pipeline);
+            }
+        }
+        self.debug_views.encode_resolve(
+            &self.device,
+            &self.queue,
+            encoder,
+            &self.hdr_view,
+            (self.debug_view, target),
+        );
     }
-}
\ No newline at end of file
+
+    /// Draws the visible parts of the selected spheres into the outline
+    /// mask, tested against the finished single-sample depth. Translucent
+    /// spheres are outlined too.
+    fn encode_selection_mask_pass(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = self.post.selection_outline().begin_mask_pass(encoder, &self.depth_view);
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, self.environment.bind_group(), &[]);
+        render_pass.set_bind_group(3, self.shadows.bind_group(), &[]);
+        let (mesh_pipeline, impostor_pipeline) =
+            (self.selection.mesh_mask_pipeline(), self.selection.impostor_mask_pipeline());
+        for list in [DrawList::Early, DrawList::Late, DrawList::Transparent] {
+            self.draw_buckets(&mut render_pass, list, mesh_pipeline, impostor_pipeline);
+        }
+    }
+
+    /// Index of the sphere drawn at pixel (`x`, `y`) of the last rendered
+    /// frame, counted from the top left, or `None` for the background.
+    /// Translucent spheres are picked like opaque ones.
+    pub async fn pick(&self, x: u32, y: u32) -> Option<u32> {
+        self.pick_rect(x, y, 1, 1).await.first().copied()
+    }
+
+    /// Indices of every sphere visible in the `width` x `height` box at
+    /// (`x`, `y`) of the last rendered frame, sorted and without repeats.
+    /// The box is clipped to the render target.
+    ///
+    /// The spheres are redrawn into an ID target from the last frame's
+    /// culling results, so spheres added or moved since show up where they
+    /// are now only if they were visible then.
+    pub async fn pick_rect(&self, x: u32, y: u32, width: u32, height: u32) -> Vec<u32> {
+        let width = x.saturating_add(width).min(self.width).saturating_sub(x);
+        let height = y.saturating_add(height).min(self.height).saturating_sub(y);
+        if width == 0 || height == 0 {
+            return Vec::new();
+        }
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Picking Encoder"),
+        });
+        {
+            let mut render_pass = self.picking.begin_pass(&mut encoder, (x, y), (width, height));
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(2, self.environment.bind_group(), &[]);
+            render_pass.set_bind_group(3, self.shadows.bind_group(), &[]);
+            let (mesh_pipeline, impostor_pipeline) = (self.picking.mesh_pipeline(), self.picking.impostor_pipeline());
+            for list in [DrawList::Early, DrawList::Late, DrawList::Transparent] {
+                self.draw_buckets(&mut render_pass, list, mesh_pipeline, impostor_pipeline);
+            }
+        }
+        let readback = self.picking.encode_readback(&self.device, &mut encoder, (x, y), (width, height));
+        let submission = self.queue.submit(std::iter::once(encoder.finish()));
+
+        // IDs are the sphere index plus one, zero is the background
+        let mut indices: Vec<u32> = readback
+            .read(&self.device, submission)
+            .await
+            .into_iter()
+            .filter_map(|id| id.checked_sub(1))
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+    }
+
+    /// Picks up the counters of an earlier frame once the GPU has written them.
+    fn poll_cull_stats(&mut self) {
+        if !self.stats_in_flight {
+            return;
+        }
+        self.device.poll(wgpu::Maintain::Poll);
+        if !self.stats_ready.swap(false, Ordering::Acquire) {
+            return;
+        }
+
+        {
+            let data = self.stats_buffer.slice(..).get_mapped_range();
+            let (draw_bytes, state_bytes) = data.split_at(self.indirect_buffer.size() as usize);
+            let state: &CullState = bytemuck::from_bytes(state_bytes);
+            // instance_count is the second word of each DrawIndexedIndirectArgs
+            let instance_counts: Vec<u32> = bytemuck::cast_slice::<u8, u32>(draw_bytes)
+                .chunks_exact(5)
+                .map(|args| args[1])
+                .collect();
+            let (early, rest) = instance_counts.split_at(LOD_BUCKET_COUNT);
+            let (late, transparent) = rest.split_at(LOD_BUCKET_COUNT);
+
+            let mut lod_drawn = [0; LOD_BUCKET_COUNT];
+            for (bucket, drawn) in lod_drawn.iter_mut().enumerate() {
+                *drawn = early[bucket] + late[bucket] + transparent[bucket];
+            }
+
+            self.cull_stats = CullStats {
+                total: self.stats_total,
+                frustum_culled: state.frustum_culled,
+                occlusion_culled: state.occlusion_culled,
+                early_drawn: early.iter().sum(),
+                late_drawn: late.iter().sum(),
+                transparent_drawn: transparent.iter().sum(),
+                lod_drawn,
+            };
+        }
+        self.stats_buffer.unmap();
+        self.stats_in_flight = false;
+    }
+}
+
+/// Near and far clip planes of a projection built like
+/// [`Camera::projection_matrix`]. The far plane is infinite for
+/// [`Mat4::perspective_infinite_rh`].
+pub(crate) fn clip_planes(projection: Mat4) -> (f32, f32) {
+    // z_axis.z = far / (near - far), w_axis.z = near * far / (near - far),
+    // which tend to -1 and -near as far goes to infinity
+    let (a, b) = (projection.z_axis.z, projection.w_axis.z);
+    if a == -1.0 {
+        return (-b, f32::INFINITY);
+    }
+    (b / a, b / (a + 1.0))
+}
+
+/// Header plus room for at least one light, with headroom for growth.
+fn create_light_buffer(device: &wgpu::Device, lights: &[GpuLight]) -> wgpu::Buffer {
+    let capacity = lights.len().max(1).next_power_of_two();
+    let header = LightHeader {
+        count: lights.len() as u32,
+        _padding: [0; 3],
+    };
+    let mut contents = bytemuck::bytes_of(&header).to_vec();
+    contents.extend_from_slice(bytemuck::cast_slice(lights));
+    contents.resize(std::mem::size_of::<LightHeader>() + capacity * std::mem::size_of::<GpuLight>(), 0);
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Light Buffer"),
+        contents: &contents,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+fn create_material_buffer(device: &wgpu::Device, materials: &[Material]) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Material Buffer"),
+        contents: bytemuck::cast_slice(materials),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+/// The buffers bound in the sphere bind group.
+struct SphereBindings<'a> {
+    spheres: &'a wgpu::Buffer,
+    lights: &'a wgpu::Buffer,
+    visible: &'a wgpu::Buffer,
+    materials: &'a wgpu::Buffer,
+    occlusion: &'a wgpu::Buffer,
+    previous_spheres: &'a wgpu::Buffer,
+    selection: &'a wgpu::Buffer,
+    light_clusters: &'a wgpu::Buffer,
+}
+
+fn create_sphere_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    bindings: &SphereBindings,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Sphere Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: bindings.spheres.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: bindings.lights.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: bindings.visible,
+                    offset: 0,
+                    size: wgpu::BufferSize::new((MAX_SPHERES * std::mem::size_of::<u32>()) as u64),
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: bindings.materials.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: bindings.occlusion.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: bindings.previous_spheres.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: bindings.selection.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: bindings.light_clusters.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+/// Mesh and impostor pipelines for the sphere passes at `sample_count`.
+/// With `ambient_target` they also write their image-based ambient light to
+/// a second target, for screen-space ambient occlusion.
+fn create_sphere_pipelines(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    vertex_shader: &wgpu::ShaderModule,
+    fragment_shader: &wgpu::ShaderModule,
+    impostor_shader: &wgpu::ShaderModule,
+    sample_count: u32,
+    ambient_target: bool,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let multisampled = sample_count > 1;
+    let target = Some(wgpu::ColorTargetState {
+        format: HDR_FORMAT,
+        blend: Some(wgpu::BlendState::REPLACE),
+        write_mask: wgpu::ColorWrites::ALL,
+    });
+    let all_targets = [target.clone(), target];
+    let targets = &all_targets[..if ambient_target { 2 } else { 1 }];
+    let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: vertex_shader,
+            entry_point: Some("vs_main"),
+            buffers: &[Vertex::layout()],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: fragment_shader,
+            entry_point: Some("fs_main"),
+            targets,
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    });
+
+    // Impostors: six vertices per instance, no vertex buffers. Under MSAA their
+    // silhouettes are antialiased through alpha-to-coverage.
+    let impostor_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Impostor Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: impostor_shader,
+            entry_point: Some("vs_impostor"),
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: impostor_shader,
+            entry_point: Some(if multisampled { "fs_impostor_multisampled" } else { "fs_impostor" }),
+            targets,
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: None,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: multisampled,
+        },
+        multiview: None,
+        cache: None,
+    });
+
+    (render_pipeline, impostor_pipeline)
+}
+
+fn create_cull_material_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    material_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Cull Material Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: material_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+fn create_hiz_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    depth_pyramid: &DepthPyramid,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("HiZ Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: depth_pyramid.buffer().as_entire_binding(),
+            },
+        ],
+    })
+}
+
+fn create_depth_view(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+    let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Depth Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    depth_texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_hdr_view(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+    let hdr_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("HDR Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    hdr_texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() <= expected.abs() * 1e-4, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn material_error_display_without_spheres() {
+        let error = MaterialError::SphereIndexOutOfRange {
+            spheres: vec![],
+            material_count: 2,
+        };
+        assert_eq!(error.to_string(), "0 sphere(s) reference materials outside 0..2");
+        let error = MaterialError::SphereIndexOutOfRange {
+            spheres: vec![4, 7],
+            material_count: 2,
+        };
+        assert_eq!(error.to_string(), "2 sphere(s) reference materials outside 0..2, first at sphere 4");
+    }
+
+    #[test]
+    fn clip_planes_of_finite_projection() {
+        let (near, far) = clip_planes(Mat4::perspective_rh(1.0, 1.5, 0.1, 100.0));
+        assert_close(near, 0.1);
+        assert_close(far, 100.0);
+    }
+
+    #[test]
+    fn clip_planes_of_infinite_projection() {
+        let (near, far) = clip_planes(Mat4::perspective_infinite_rh(1.0, 1.5, 0.1));
+        assert_close(near, 0.1);
+        assert_eq!(far, f32::INFINITY);
+
+        let (near, far) = LightClusters::depth_range((near, far));
+        assert!(far.is_finite() && far > near);
+    }
+}
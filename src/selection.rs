@@ -0,0 +1,561 @@
+use std::ops::Range;
+
+use glam::Vec3;
+
+use crate::mesh::Vertex;
+use crate::post::{begin_fullscreen_pass, FullscreenPipelines, PostContext, PostEffect, PostTarget};
+use crate::renderer::MAX_SPHERES;
+
+const MASK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+// One bit per sphere the sphere buffer can hold
+const WORD_COUNT: usize = MAX_SPHERES.div_ceil(32);
+
+/// Widest outline in pixels. The outline pass searches a square twice this
+/// wide around every pixel outside the selection.
+pub const MAX_OUTLINE_WIDTH: f32 = 8.0;
+
+/// How selected spheres are highlighted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SelectionSettings {
+    /// Display color of the outline drawn around the visible parts of the
+    /// selection after tone mapping.
+    pub outline_color: Vec3,
+    /// Outline width in pixels, up to [`MAX_OUTLINE_WIDTH`]. `0.0` turns
+    /// the outline off.
+    pub outline_width: f32,
+    /// Linear color blended over the shading of selected spheres.
+    pub tint_color: Vec3,
+    /// Blend factor of the tint. `0.0` leaves the shading alone.
+    pub tint_strength: f32,
+}
+
+impl Default for SelectionSettings {
+    fn default() -> Self {
+        Self {
+            outline_color: Vec3::new(1.0, 0.55, 0.1),
+            outline_width: 2.0,
+            tint_color: Vec3::new(1.0, 0.55, 0.1),
+            tint_strength: 0.0,
+        }
+    }
+}
+
+/// Mirrors the header of `Selection` in fragment.wgsl, which the bits
+/// follow.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SelectionHeader {
+    tint: [f32; 4],
+}
+
+/// Which spheres are selected, as a bitset indexed like the sphere buffer.
+/// Changing a range costs one word per 32 spheres however large the
+/// selection, and the words changed since [`Self::take_dirty`] are tracked
+/// so only those need uploading.
+struct SelectionBits {
+    words: Vec<u32>,
+    // Words changed since the last take_dirty
+    dirty: Option<Range<usize>>,
+    count: usize,
+}
+
+impl SelectionBits {
+    fn new() -> Self {
+        Self {
+            words: vec![0; WORD_COUNT],
+            dirty: None,
+            count: 0,
+        }
+    }
+
+    fn contains(&self, index: u32) -> bool {
+        let index = index as usize;
+        index < MAX_SPHERES && self.words[index / 32] & (1 << (index % 32)) != 0
+    }
+
+    fn set(&mut self, indices: Range<u32>, selected: bool) {
+        if selected {
+            self.update(indices, |word, mask| word | mask);
+        } else {
+            self.update(indices, |word, mask| word & !mask);
+        }
+    }
+
+    fn toggle(&mut self, indices: Range<u32>) {
+        self.update(indices, |word, mask| word ^ mask);
+    }
+
+    fn clear(&mut self) {
+        if self.count > 0 {
+            self.set(0..MAX_SPHERES as u32, false);
+        }
+    }
+
+    /// Applies `op` to each word the range touches, with a mask of the bits
+    /// of the range within that word.
+    fn update(&mut self, indices: Range<u32>, op: impl Fn(u32, u32) -> u32) {
+        let start = (indices.start as usize).min(MAX_SPHERES);
+        let end = (indices.end as usize).min(MAX_SPHERES);
+        if start >= end {
+            return;
+        }
+
+        let (first, last) = (start / 32, (end - 1) / 32);
+        for index in first..=last {
+            let low = if index == first { start % 32 } else { 0 };
+            let high = if index == last { (end - 1) % 32 + 1 } else { 32 };
+            let mask = (u32::MAX >> (32 - (high - low))) << low;
+
+            let word = &mut self.words[index];
+            let updated = op(*word, mask);
+            self.count = self.count + updated.count_ones() as usize - word.count_ones() as usize;
+            *word = updated;
+        }
+
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => dirty.start.min(first)..dirty.end.max(last + 1),
+            None => first..last + 1,
+        });
+    }
+
+    /// Index of the first word changed since the last call, and the words
+    /// from there to the last changed one.
+    fn take_dirty(&mut self) -> Option<(usize, &[u32])> {
+        self.dirty.take().map(|dirty| (dirty.start, &self.words[dirty]))
+    }
+}
+
+/// The selection bits, their GPU copy and the pipelines that draw the
+/// selected spheres into the outline mask.
+pub(crate) struct Selection {
+    bits: SelectionBits,
+    buffer: wgpu::Buffer,
+    mesh_mask_pipeline: wgpu::RenderPipeline,
+    impostor_mask_pipeline: wgpu::RenderPipeline,
+}
+
+impl Selection {
+    /// `sphere_layout` is the pipeline layout of the sphere passes, whose
+    /// bind groups the mask pass reuses.
+    pub fn new(device: &wgpu::Device, sphere_layout: &wgpu::PipelineLayout, depth_format: wgpu::TextureFormat) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Selection Mask Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/brdf.wgsl"),
+                    "\n",
+                    include_str!("shaders/fragment.wgsl"),
+                    "\n",
+                    include_str!("shaders/impostor.wgsl"),
+                    "\n",
+                    include_str!("shaders/selection.wgsl"),
+                )
+                .into(),
+            ),
+        });
+
+        // Created zeroed, so nothing starts out selected
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Selection Buffer"),
+            size: (std::mem::size_of::<SelectionHeader>() + WORD_COUNT * std::mem::size_of::<u32>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Tested against the finished depth without writing it
+        let depth_stencil = wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        };
+        let mask_target = wgpu::ColorTargetState {
+            format: MASK_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        };
+        let mesh_mask_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mesh Selection Mask Pipeline"),
+            layout: Some(sphere_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_selection_mask"),
+                buffers: &[Vertex::layout()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_selection_mask"),
+                targets: &[Some(mask_target.clone())],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(depth_stencil.clone()),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let impostor_mask_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Impostor Selection Mask Pipeline"),
+            layout: Some(sphere_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_impostor_selection_mask"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_impostor_selection_mask"),
+                targets: &[Some(mask_target)],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(depth_stencil),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            bits: SelectionBits::new(),
+            buffer,
+            mesh_mask_pipeline,
+            impostor_mask_pipeline,
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn mesh_mask_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.mesh_mask_pipeline
+    }
+
+    pub fn impostor_mask_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.impostor_mask_pipeline
+    }
+
+    /// Number of selected spheres.
+    pub fn count(&self) -> usize {
+        self.bits.count
+    }
+
+    pub fn contains(&self, index: u32) -> bool {
+        self.bits.contains(index)
+    }
+
+    /// Selects or deselects `indices`, clipped to [`MAX_SPHERES`].
+    pub fn set(&mut self, indices: Range<u32>, selected: bool) {
+        self.bits.set(indices, selected);
+    }
+
+    /// Flips the selection of `indices`, clipped to [`MAX_SPHERES`].
+    pub fn toggle(&mut self, indices: Range<u32>) {
+        self.bits.toggle(indices);
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.clear();
+    }
+
+    pub fn set_tint(&self, queue: &wgpu::Queue, color: Vec3, strength: f32) {
+        let header = SelectionHeader {
+            tint: color.extend(strength.clamp(0.0, 1.0)).to_array(),
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&header));
+    }
+
+    /// Writes the words changed since the last call to the GPU copy.
+    pub fn upload(&mut self, queue: &wgpu::Queue) {
+        if let Some((first, words)) = self.bits.take_dirty() {
+            let offset = std::mem::size_of::<SelectionHeader>() + first * std::mem::size_of::<u32>();
+            queue.write_buffer(&self.buffer, offset as u64, bytemuck::cast_slice(words));
+        }
+    }
+}
+
+/// Mirrors `Params` in outline.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    width: f32,
+    encode_srgb: u32,
+    _padding: [u32; 2],
+}
+
+/// The built-in post step that outlines the selection. Owns the mask the
+/// renderer draws the visible selected spheres into each frame, and is
+/// skipped while nothing is selected.
+pub(crate) struct SelectionOutline {
+    color: Vec3,
+    width: f32,
+    active: bool,
+    mask_view: wgpu::TextureView,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipelines: FullscreenPipelines,
+}
+
+impl SelectionOutline {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Selection Outline Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(include_str!("shaders/srgb.wgsl"), "\n", include_str!("shaders/outline.wgsl")).into(),
+            ),
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Selection Outline Params Buffer"),
+            size: std::mem::size_of::<OutlineUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Selection Outline Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                texture_entry(2),
+            ],
+        });
+
+        let pipelines = FullscreenPipelines::new(
+            device,
+            "Selection Outline Pipeline",
+            shader,
+            &[&bind_group_layout],
+            "fs_outline",
+        );
+
+        let settings = SelectionSettings::default();
+        Self {
+            color: settings.outline_color,
+            width: settings.outline_width,
+            active: false,
+            mask_view: create_mask_view(device, width, height),
+            uniform_buffer,
+            bind_group_layout,
+            pipelines,
+        }
+    }
+
+    pub fn set_outline(&mut self, color: Vec3, width: f32) {
+        self.color = color;
+        self.width = width.clamp(0.0, MAX_OUTLINE_WIDTH);
+    }
+
+    /// Set while anything is selected. The renderer only draws the mask
+    /// for frames where the outline is enabled.
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    /// Starts the mask pass with a cleared mask, testing against `depth_view`.
+    pub fn begin_mask_pass<'a>(
+        &self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth_view: &wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Selection Mask Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.mask_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        })
+    }
+}
+
+impl PostEffect for SelectionOutline {
+    fn label(&self) -> &str {
+        "Selection Outline Pass"
+    }
+
+    fn enabled(&self) -> bool {
+        self.active && self.width > 0.0
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.mask_view = create_mask_view(device, width, height);
+    }
+
+    fn encode(&mut self, context: &mut PostContext, input: &wgpu::TextureView, output: &PostTarget) {
+        let uniform = OutlineUniform {
+            color: self.color.extend(1.0).to_array(),
+            width: self.width,
+            encode_srgb: output.encode_srgb as u32,
+            _padding: [0; 2],
+        };
+        context.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let bind_group = context.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Selection Outline Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(input),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.mask_view),
+                },
+            ],
+        });
+
+        let pipeline = self.pipelines.get(context.device, output.format);
+        let mut pass = begin_fullscreen_pass(context.encoder, "Selection Outline Pass", output.view);
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_mask_view(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("Selection Mask Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: MASK_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_inside_one_word() {
+        let mut bits = SelectionBits::new();
+        bits.set(35..40, true);
+        assert_eq!(bits.count, 5);
+        assert_eq!(bits.words[1], 0b11111 << 3);
+        assert!(!bits.contains(34) && bits.contains(35) && bits.contains(39) && !bits.contains(40));
+        assert_eq!(bits.take_dirty(), Some((1, &[0b11111 << 3][..])));
+        assert_eq!(bits.take_dirty(), None);
+    }
+
+    #[test]
+    fn range_across_words() {
+        let mut bits = SelectionBits::new();
+        bits.set(30..100, true);
+        assert_eq!(bits.count, 70);
+        assert_eq!(bits.words[0], 0b11 << 30);
+        assert_eq!(bits.words[1], u32::MAX);
+        assert_eq!(bits.words[2], u32::MAX);
+        assert_eq!(bits.words[3], 0b1111);
+        assert!(!bits.contains(29) && bits.contains(30) && bits.contains(99) && !bits.contains(100));
+
+        // Deselecting in the middle touches only the middle words
+        bits.take_dirty();
+        bits.set(64..96, false);
+        assert_eq!(bits.count, 38);
+        assert_eq!(bits.take_dirty().map(|(first, words)| (first, words.len())), Some((2, 1)));
+    }
+
+    #[test]
+    fn dirty_range_spans_every_change() {
+        let mut bits = SelectionBits::new();
+        bits.set(100..101, true);
+        bits.set(3..4, true);
+        let (first, words) = bits.take_dirty().unwrap();
+        assert_eq!((first, words.len()), (0, 4));
+    }
+
+    #[test]
+    fn ranges_are_clipped_to_max_spheres() {
+        let mut bits = SelectionBits::new();
+        let max = MAX_SPHERES as u32;
+        bits.set(max - 10..max + 1000, true);
+        assert_eq!(bits.count, 10);
+        assert!(bits.contains(max - 1) && !bits.contains(max));
+
+        bits.set(max..u32::MAX, true);
+        bits.toggle(max + 5..max + 6);
+        assert_eq!(bits.count, 10);
+        bits.set(50..50, true);
+        assert_eq!(bits.count, 10);
+    }
+
+    #[test]
+    fn toggle_and_clear_counts() {
+        let mut bits = SelectionBits::new();
+        bits.set(30..70, true);
+        bits.toggle(45..55);
+        assert_eq!(bits.count, 30);
+        assert!(bits.contains(44) && !bits.contains(45) && !bits.contains(54) && bits.contains(55));
+
+        bits.toggle(0..100);
+        assert_eq!(bits.count, 70);
+        assert!(!bits.contains(30) && bits.contains(50) && bits.contains(99));
+
+        bits.set(50..51, true);
+        assert_eq!(bits.count, 70);
+        bits.take_dirty();
+        bits.clear();
+        assert_eq!(bits.count, 0);
+        assert!(bits.words.iter().all(|word| *word == 0));
+        assert_eq!(bits.take_dirty().map(|(first, words)| (first, words.len())), Some((0, WORD_COUNT)));
+
+        // Clearing an empty selection uploads nothing
+        bits.clear();
+        assert_eq!(bits.take_dirty(), None);
+    }
+}
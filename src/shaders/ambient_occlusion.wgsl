@@ -0,0 +1,96 @@
+// Sphere-to-sphere ambient occlusion. Every sphere gathers its neighbours
+// from a uniform grid and sums how much of its surroundings they cover,
+// projected onto L0 + L1 so the lighting shaders can evaluate it for any
+// normal: occlusion(n) = w / 4 + dot(n, xyz) / 2.
+
+struct Sphere {
+    position: vec3<f32>,
+    radius: f32,
+    material_index: u32,
+    padding: array<u32, 3>,
+};
+
+struct Params {
+    grid_origin: vec3<f32>,
+    cell_size: f32,
+    grid_dims: vec3<u32>,
+    sphere_count: u32,
+    distance: f32,
+    strength: f32,
+    padding: vec2<f32>,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> spheres: array<Sphere>;
+// Start of each cell's run of sphere indices, followed by the end offset
+@group(0) @binding(2) var<storage, read> cell_offsets: array<u32>;
+@group(0) @binding(3) var<storage, read> cell_entries: array<u32>;
+@group(0) @binding(4) var<storage, read_write> occlusion: array<vec4<f32>>;
+
+// Same clamping as SphereGrid::build
+fn cell_coords(point: vec3<f32>) -> vec3<u32> {
+    let cell = max(floor((point - params.grid_origin) / params.cell_size), vec3<f32>(0.0));
+    return min(vec3<u32>(cell), params.grid_dims - 1u);
+}
+
+// Occlusion by `other` as seen from the center of `sphere`: the
+// cosine-weighted solid angle (r / d)^2 of a sphere straight above the
+// normal, faded out as the gap approaches params.distance
+fn sphere_occlusion(sphere: Sphere, other: Sphere) -> vec4<f32> {
+    let offset = other.position - sphere.position;
+    let center_distance = length(offset);
+    let gap = center_distance - sphere.radius - other.radius;
+    if (center_distance < 1e-6 || gap >= params.distance) {
+        return vec4<f32>(0.0);
+    }
+
+    // Clamped so an enclosing occluder covers at most everything
+    let distance = max(center_distance, other.radius);
+    let coverage = other.radius * other.radius / (distance * distance);
+    let fade = 1.0 - smoothstep(0.5 * params.distance, params.distance, gap);
+    let weight = coverage * fade * params.strength;
+    return vec4<f32>(offset / center_distance * weight, weight);
+}
+
+@compute @workgroup_size(256)
+fn cs_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let index = global_id.x;
+    if (index >= params.sphere_count) {
+        return;
+    }
+    let sphere = spheres[index];
+
+    // Any occluder in reach overlaps this box
+    let reach = vec3<f32>(sphere.radius + params.distance);
+    let query_min = sphere.position - reach;
+    let query_max = sphere.position + reach;
+    let first = cell_coords(query_min);
+    let last = cell_coords(query_max);
+
+    var sum = vec4<f32>(0.0);
+    for (var z = first.z; z <= last.z; z++) {
+        for (var y = first.y; y <= last.y; y++) {
+            for (var x = first.x; x <= last.x; x++) {
+                let cell = vec3<u32>(x, y, z);
+                let cell_index = (z * params.grid_dims.y + y) * params.grid_dims.x + x;
+                for (var entry = cell_offsets[cell_index]; entry < cell_offsets[cell_index + 1u]; entry++) {
+                    let other_index = cell_entries[entry];
+                    if (other_index == index) {
+                        continue;
+                    }
+                    let other = spheres[other_index];
+
+                    // Spheres are listed in every cell they touch; count each
+                    // pair once, in the cell holding the corner of their overlap
+                    let overlap_min = max(query_min, other.position - other.radius);
+                    let overlap_max = min(query_max, other.position + other.radius);
+                    if (any(overlap_min > overlap_max) || any(cell_coords(overlap_min) != cell)) {
+                        continue;
+                    }
+                    sum += sphere_occlusion(sphere, other);
+                }
+            }
+        }
+    }
+    occlusion[index] = sum;
+}
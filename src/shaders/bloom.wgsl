@@ -0,0 +1,132 @@
+// Physically based bloom after Jimenez, "Next Generation Post Processing in
+// Call of Duty: Advanced Warfare". The scene is filtered down a chain of half
+// resolution levels, then the levels are tent-filtered back up and summed.
+// There is no threshold: the composite pass mixes a fraction of the result
+// into the scene, so the total energy stays the same. Concatenated after
+// srgb.wgsl.
+
+struct Params {
+    // Tent filter spread in source texels
+    radius: f32,
+    // One over the number of summed levels, so the result averages them
+    level_scale: f32,
+    // Fraction of the scene the composite pass replaces with the bloom
+    intensity: f32,
+    // Set when the composite pass writes the final non-sRGB target
+    encode_srgb: u32,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var source: texture_2d<f32>;
+@group(0) @binding(2) var source_sampler: sampler;
+// The unfiltered scene, only bound for the composite pass
+@group(1) @binding(0) var scene: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    // Levels are rounded down, so the target's pixel grid isn't always
+    // exactly twice or half the source's; the interpolated uv always lines up
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn sample_offset(uv: vec2<f32>, texel: vec2<f32>, offset: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(source, source_sampler, uv + offset * texel, 0.0).rgb;
+}
+
+// Weights a group of samples by 1 / (1 + luma) so single very bright pixels
+// don't turn into flickering blobs
+fn karis_average(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>, d: vec3<f32>) -> vec4<f32> {
+    let average = (a + b + c + d) * 0.25;
+    let weight = 1.0 / (1.0 + dot(average, vec3<f32>(0.2126, 0.7152, 0.0722)));
+    return vec4<f32>(average * weight, weight);
+}
+
+struct Taps {
+    a: vec3<f32>, b: vec3<f32>, c: vec3<f32>,
+    d: vec3<f32>, e: vec3<f32>, f: vec3<f32>,
+    g: vec3<f32>, h: vec3<f32>, i: vec3<f32>,
+    j: vec3<f32>, k: vec3<f32>,
+    l: vec3<f32>, m: vec3<f32>,
+};
+
+// 13 bilinear taps covering a 4x4 texel footprint around the target pixel
+fn downsample_taps(uv: vec2<f32>) -> Taps {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    var taps: Taps;
+    taps.a = sample_offset(uv, texel, vec2<f32>(-2.0, -2.0));
+    taps.b = sample_offset(uv, texel, vec2<f32>(0.0, -2.0));
+    taps.c = sample_offset(uv, texel, vec2<f32>(2.0, -2.0));
+    taps.d = sample_offset(uv, texel, vec2<f32>(-2.0, 0.0));
+    taps.e = sample_offset(uv, texel, vec2<f32>(0.0, 0.0));
+    taps.f = sample_offset(uv, texel, vec2<f32>(2.0, 0.0));
+    taps.g = sample_offset(uv, texel, vec2<f32>(-2.0, 2.0));
+    taps.h = sample_offset(uv, texel, vec2<f32>(0.0, 2.0));
+    taps.i = sample_offset(uv, texel, vec2<f32>(2.0, 2.0));
+    taps.j = sample_offset(uv, texel, vec2<f32>(-1.0, -1.0));
+    taps.k = sample_offset(uv, texel, vec2<f32>(1.0, -1.0));
+    taps.l = sample_offset(uv, texel, vec2<f32>(-1.0, 1.0));
+    taps.m = sample_offset(uv, texel, vec2<f32>(1.0, 1.0));
+    return taps;
+}
+
+// First level: Karis average of the five overlapping 2x2 groups
+@fragment
+fn fs_downsample_first(in: VertexOutput) -> @location(0) vec4<f32> {
+    let t = downsample_taps(in.uv);
+    var sum = karis_average(t.j, t.k, t.l, t.m) * 0.5;
+    sum += karis_average(t.a, t.b, t.d, t.e) * 0.125;
+    sum += karis_average(t.b, t.c, t.e, t.f) * 0.125;
+    sum += karis_average(t.d, t.e, t.g, t.h) * 0.125;
+    sum += karis_average(t.e, t.f, t.h, t.i) * 0.125;
+    return vec4<f32>(sum.rgb / sum.w, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let t = downsample_taps(in.uv);
+    var color = t.e * 0.125;
+    color += (t.a + t.c + t.g + t.i) * 0.03125;
+    color += (t.b + t.d + t.f + t.h) * 0.0625;
+    color += (t.j + t.k + t.l + t.m) * 0.125;
+    return vec4<f32>(color, 1.0);
+}
+
+// 3x3 tent filter, reading the smaller level
+fn tent(uv: vec2<f32>) -> vec3<f32> {
+    let texel = params.radius / vec2<f32>(textureDimensions(source));
+    var color = sample_offset(uv, texel, vec2<f32>(0.0, 0.0)) * 4.0;
+    color += sample_offset(uv, texel, vec2<f32>(0.0, -1.0)) * 2.0;
+    color += sample_offset(uv, texel, vec2<f32>(-1.0, 0.0)) * 2.0;
+    color += sample_offset(uv, texel, vec2<f32>(1.0, 0.0)) * 2.0;
+    color += sample_offset(uv, texel, vec2<f32>(0.0, 1.0)) * 2.0;
+    color += sample_offset(uv, texel, vec2<f32>(-1.0, -1.0));
+    color += sample_offset(uv, texel, vec2<f32>(1.0, -1.0));
+    color += sample_offset(uv, texel, vec2<f32>(-1.0, 1.0));
+    color += sample_offset(uv, texel, vec2<f32>(1.0, 1.0));
+    return color / 16.0;
+}
+
+// Added onto the next larger level by the blend state
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(tent(in.uv), 1.0);
+}
+
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureLoad(scene, vec2<i32>(in.position.xy), 0);
+    var mixed = mix(color.rgb, tent(in.uv) * params.level_scale, params.intensity);
+    if (params.encode_srgb != 0u) {
+        mixed = srgb_encode(clamp(mixed, vec3<f32>(0.0), vec3<f32>(1.0)));
+    }
+    return vec4<f32>(mixed, color.a);
+}
@@ -0,0 +1,26 @@
+// BRDF terms shared by the lighting and environment baking shaders
+
+const PI: f32 = 3.14159265359;
+
+// Trowbridge-Reitz (GGX) normal distribution, alpha = roughness^2
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha_sq = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha_sq - 1.0) + 1.0;
+    return alpha_sq / (PI * d * d);
+}
+
+// Height-correlated Smith visibility; includes the 1 / (4 N.L N.V) term
+fn visibility_smith_ggx(n_dot_l: f32, n_dot_v: f32, alpha: f32) -> f32 {
+    let alpha_sq = alpha * alpha;
+    let ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - alpha_sq) + alpha_sq);
+    let ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - alpha_sq) + alpha_sq);
+    let ggx = ggx_l + ggx_v;
+    if (ggx > 0.0) {
+        return 0.5 / ggx;
+    }
+    return 0.0;
+}
+
+fn fresnel_schlick(f0: vec3<f32>, v_dot_h: f32) -> vec3<f32> {
+    return f0 + (vec3<f32>(1.0) - f0) * pow(clamp(1.0 - v_dot_h, 0.0, 1.0), 5.0);
+}
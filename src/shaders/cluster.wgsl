@@ -0,0 +1,127 @@
+// Clustered light assignment. The view frustum is split into screen tiles
+// and exponentially spaced depth slices, and every cluster lists the lights
+// whose range reaches its view-space bounding box. fragment.wgsl then only
+// shades with the lights of the cluster a fragment falls in.
+
+// Must match fragment.wgsl
+struct Light {
+    position: vec3<f32>,
+    range: f32,
+    direction: vec3<f32>,
+    kind: u32,
+    color: vec3<f32>,
+    intensity: f32,
+    angle_scale: f32,
+    angle_offset: f32,
+    shadow: u32,
+    padding: u32,
+};
+
+struct Lights {
+    count: u32,
+    padding: array<u32, 3>,
+    items: array<Light>,
+};
+
+const LIGHT_DIRECTIONAL: u32 = 1u;
+
+// Must match cluster.rs
+const CLUSTER_DIMS: vec3<u32> = vec3<u32>(16u, 9u, 24u);
+const CLUSTER_STRIDE: u32 = 128u;
+
+struct Params {
+    view: mat4x4<f32>,
+    // Of the jittered projection the sphere passes draw with
+    inverse_projection: mat4x4<f32>,
+    // Near and far depth of the slices in x and y
+    clip_planes: vec4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> lights: Lights;
+// Per cluster a light count followed by that many light indices
+@group(0) @binding(2) var<storage, read_write> clusters: array<u32>;
+
+const BATCH_SIZE: u32 = 64u;
+
+// View-space position and range of the lights being tested. A negative
+// range reaches every cluster.
+var<workgroup> batch: array<vec4<f32>, BATCH_SIZE>;
+
+// View depth where slice `slice` starts
+fn slice_depth(slice: u32) -> f32 {
+    let near = params.clip_planes.x;
+    let far = params.clip_planes.y;
+    return near * pow(far / near, f32(slice) / f32(CLUSTER_DIMS.z));
+}
+
+// View-space point at view depth `depth` on the ray through `ndc`
+fn view_point(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let point = params.inverse_projection * vec4<f32>(ndc, 0.0, 1.0);
+    let ray = point.xyz / point.w;
+    return ray * (depth / -ray.z);
+}
+
+// One invocation per cluster. The workgroup shares each batch of lights,
+// so every light is read and transformed once per workgroup.
+@compute @workgroup_size(64)
+fn cs_main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    let cluster = id.x;
+    let in_grid = cluster < CLUSTER_DIMS.x * CLUSTER_DIMS.y * CLUSTER_DIMS.z;
+    let coords = vec3<u32>(
+        cluster % CLUSTER_DIMS.x,
+        (cluster / CLUSTER_DIMS.x) % CLUSTER_DIMS.y,
+        cluster / (CLUSTER_DIMS.x * CLUSTER_DIMS.y),
+    );
+
+    // Bounds of the cluster's eight corners
+    let ndc_min = vec2<f32>(coords.xy) / vec2<f32>(CLUSTER_DIMS.xy) * 2.0 - 1.0;
+    let ndc_max = vec2<f32>(coords.xy + 1u) / vec2<f32>(CLUSTER_DIMS.xy) * 2.0 - 1.0;
+    let depth_min = slice_depth(coords.z);
+    let depth_max = slice_depth(coords.z + 1u);
+    var box_min = vec3<f32>(3.4e38);
+    var box_max = vec3<f32>(-3.4e38);
+    for (var corner = 0u; corner < 8u; corner++) {
+        let ndc = select(ndc_min, ndc_max, vec2<bool>((corner & 1u) != 0u, (corner & 2u) != 0u));
+        let point = view_point(ndc, select(depth_min, depth_max, (corner & 4u) != 0u));
+        box_min = min(box_min, point);
+        box_max = max(box_max, point);
+    }
+
+    let base = cluster * CLUSTER_STRIDE;
+    var count = 0u;
+    let light_count = lights.count;
+    for (var first = 0u; first < light_count; first += BATCH_SIZE) {
+        let index = first + local_index;
+        if (index < light_count) {
+            let light = lights.items[index];
+            // Directional lights and lights without a cutoff reach everywhere
+            var range = light.range;
+            if (light.kind == LIGHT_DIRECTIONAL || range <= 0.0) {
+                range = -1.0;
+            }
+            batch[local_index] = vec4<f32>((params.view * vec4<f32>(light.position, 1.0)).xyz, range);
+        }
+        workgroupBarrier();
+
+        let batch_count = min(BATCH_SIZE, light_count - first);
+        for (var i = 0u; i < batch_count; i++) {
+            let light = batch[i];
+            let offset = light.xyz - clamp(light.xyz, box_min, box_max);
+            let reaches = light.w < 0.0 || dot(offset, offset) <= light.w * light.w;
+            // Lights past a full cluster are dropped
+            if (in_grid && reaches && count < CLUSTER_STRIDE - 1u) {
+                clusters[base + 1u + count] = first + i;
+                count++;
+            }
+        }
+        workgroupBarrier();
+    }
+
+    if (in_grid) {
+        clusters[base] = count;
+    }
+}
@@ -2,35 +2,221 @@ struct Sphere {
     position: vec3<f32>,
     radius: f32,
     material_index: u32,
-    padding: vec3<u32>,
+    padding: array<u32, 3>,
 };
 
+// Only the alpha is read, to route translucent spheres
+struct Material {
+    base_color: vec4<f32>,
+    metallic_roughness: vec2<f32>,
+    emission: array<f32, 3>,
+    padding: array<f32, 3>,
+};
+
+// Matches wgpu's DrawIndexedIndirectArgs. Impostor draws reuse the first
+// four words as DrawIndirectArgs (vertex_count = 6, zero offsets).
 struct DrawCommand {
-    vertex_count: u32,
-    instance_count: u32,
-    base_vertex: u32,
-    base_instance: u32,
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+// The first three words double as the indirect dispatch of the late pass
+struct CullState {
+    late_dispatch_x: atomic<u32>,
+    late_dispatch_y: u32,
+    late_dispatch_z: u32,
+    recheck_count: atomic<u32>,
+    frustum_culled: atomic<u32>,
+    occlusion_culled: atomic<u32>,
+};
+
+struct CullParams {
+    view_proj: mat4x4<f32>,
+    planes: array<vec4<f32>, 6>,
+    // Minimum projected radius in pixels for each mesh level
+    lod_thresholds: vec4<f32>,
+    sphere_count: u32,
+    occlusion_enabled: u32,
+    hiz_levels: u32,
+    // Projected radius in pixels = radius * lod_scale / clip.w
+    lod_scale: f32,
+    hiz_size: vec2<u32>,
+    impostors_only: u32,
+    bucket_capacity: u32,
 };
 
-@group(0) @binding(0) var<storage, read_write> spheres: array<Sphere>;
-@group(1) @binding(0) var<storage, read> sphere_data: array<Sphere>;
-@group(1) @binding(1) var<uniform> light: vec4<f32>;
-@group(0) @binding(1) var<storage, read_write> draw_commands: array<DrawCommand>;
-@group(0) @binding(2) var<storage, read_write> visible_spheres: array<Sphere>;
+// One bucket per mesh level (SphereMesh::LEVEL_COUNT), then the impostor bucket
+const LEVEL_COUNT: u32 = 4u;
+const BUCKET_COUNT: u32 = 5u;
+const IMPOSTOR_BUCKET: u32 = 4u;
+// Draw list of the translucent spheres either pass lets through, after the
+// early and late lists
+const TRANSPARENT_LIST: u32 = 2u;
+
+@group(0) @binding(0) var<storage, read> spheres: array<Sphere>;
+// Indexed by list * BUCKET_COUNT + bucket: early, late, then transparent
+@group(0) @binding(1) var<storage, read_write> draw_commands: array<DrawCommand, 15>;
+// Same layout as draw_commands, `bucket_capacity` entries per bucket
+@group(0) @binding(2) var<storage, read_write> visible_spheres: array<u32>;
+@group(0) @binding(3) var<uniform> params: CullParams;
+@group(0) @binding(4) var<storage, read_write> recheck_spheres: array<u32>;
+@group(0) @binding(5) var<storage, read_write> state: CullState;
+// Max-depth pyramid, see hiz.wgsl; rebuilt whenever the render targets are resized
+@group(1) @binding(0) var<storage, read> hiz: array<f32>;
+// Own group since the buffer is reallocated when the material list grows
+@group(2) @binding(0) var<storage, read> materials: array<Material>;
+
+fn hiz_level_size(level: u32) -> vec2<u32> {
+    return max(params.hiz_size >> vec2<u32>(level), vec2<u32>(1u));
+}
+
+fn hiz_load(level: u32, coord: vec2<u32>) -> f32 {
+    var offset = 0u;
+    for (var i = 0u; i < level; i++) {
+        let size = hiz_level_size(i);
+        offset += size.x * size.y;
+    }
+    return hiz[offset + coord.y * hiz_level_size(level).x + coord.x];
+}
+
+fn in_frustum(center: vec3<f32>, radius: f32) -> bool {
+    for (var i = 0u; i < 6u; i++) {
+        let plane = params.planes[i];
+        if (dot(plane.xyz, center) + plane.w < -radius) {
+            return false;
+        }
+    }
+    return true;
+}
+
+// Conservative test of the sphere's screen-space bounds against the max-depth
+// pyramid. Returns true only if every covered texel is nearer than the sphere.
+fn is_occluded(center: vec3<f32>, radius: f32) -> bool {
+    var uv_min = vec2<f32>(1.0);
+    var uv_max = vec2<f32>(0.0);
+    var nearest = 1.0;
+
+    for (var i = 0u; i < 8u; i++) {
+        let corner = center + radius * vec3<f32>(
+            select(-1.0, 1.0, (i & 1u) != 0u),
+            select(-1.0, 1.0, (i & 2u) != 0u),
+            select(-1.0, 1.0, (i & 4u) != 0u),
+        );
+        let clip = params.view_proj * vec4<f32>(corner, 1.0);
+        if (clip.w <= 0.0 || clip.z < 0.0) {
+            // Crosses the near plane; the projected bounds are meaningless
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        uv_min = min(uv_min, uv);
+        uv_max = max(uv_max, uv);
+        nearest = min(nearest, ndc.z);
+    }
+
+    uv_min = clamp(uv_min, vec2<f32>(0.0), vec2<f32>(1.0));
+    uv_max = clamp(uv_max, vec2<f32>(0.0), vec2<f32>(1.0));
+
+    // Pick the level where the bounds span at most 2x2 texels
+    let extent = (uv_max - uv_min) * vec2<f32>(params.hiz_size);
+    let level = min(
+        u32(ceil(log2(max(max(extent.x, extent.y), 1.0)))),
+        params.hiz_levels - 1u,
+    );
+
+    let size = hiz_level_size(level);
+    let lo = min(vec2<u32>(uv_min * vec2<f32>(size)), size - 1u);
+    let hi = min(vec2<u32>(uv_max * vec2<f32>(size)), size - 1u);
+
+    let farthest = max(
+        max(hiz_load(level, lo), hiz_load(level, vec2<u32>(hi.x, lo.y))),
+        max(hiz_load(level, vec2<u32>(lo.x, hi.y)), hiz_load(level, hi)),
+    );
+
+    return nearest > farthest;
+}
+
+fn lod_bucket(center: vec3<f32>, radius: f32) -> u32 {
+    if (params.impostors_only != 0u) {
+        return IMPOSTOR_BUCKET;
+    }
+
+    let w = (params.view_proj * vec4<f32>(center, 1.0)).w;
+    // Orthographic (shadow) views have a constant w of 1 and can't be inside a sphere
+    let orthographic = all(vec3<f32>(params.view_proj[0][3], params.view_proj[1][3], params.view_proj[2][3]) == vec3<f32>(0.0));
+    if (!orthographic && w <= radius) {
+        return LEVEL_COUNT - 1u;
+    }
 
+    // Sub-pixel spheres stay in the impostor bucket as billboards
+    let radius_px = radius * params.lod_scale / w;
+    var bucket = IMPOSTOR_BUCKET;
+    for (var level = 0u; level < LEVEL_COUNT; level++) {
+        if (radius_px >= params.lod_thresholds[level]) {
+            bucket = level;
+        }
+    }
+    return bucket;
+}
+
+fn emit(index: u32, sphere: Sphere, pass_index: u32) {
+    var list = pass_index;
+    if (materials[sphere.material_index].base_color.a < 1.0) {
+        list = TRANSPARENT_LIST;
+    }
+    let command = list * BUCKET_COUNT + lod_bucket(sphere.position, sphere.radius);
+    let slot = atomicAdd(&draw_commands[command].instance_count, 1u);
+    visible_spheres[command * params.bucket_capacity + slot] = index;
+}
+
+// Early pass: frustum test, then occlusion against last frame's pyramid.
+// Spheres that look occluded are queued for the late pass instead of
+// being dropped, since the old pyramid may be stale.
 @compute @workgroup_size(256)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let index = global_id.x;
-    if (index >= arrayLength(&spheres)) {
+    if (index >= params.sphere_count) {
+        return;
+    }
+
+    let sphere = spheres[index];
+    if (!in_frustum(sphere.position, sphere.radius)) {
+        atomicAdd(&state.frustum_culled, 1u);
+        return;
+    }
+
+    if (params.occlusion_enabled != 0u && is_occluded(sphere.position, sphere.radius)) {
+        let slot = atomicAdd(&state.recheck_count, 1u);
+        recheck_spheres[slot] = index;
+        // One late workgroup per 256 queued spheres
+        if (slot % 256u == 0u) {
+            atomicAdd(&state.late_dispatch_x, 1u);
+        }
+        return;
+    }
+
+    // Compact survivors; the render pass draws `instance_count` of each bucket
+    emit(index, sphere, 0u);
+}
+
+// Late pass: retest the queued spheres against the pyramid rebuilt from
+// this frame's early depth.
+@compute @workgroup_size(256)
+fn main_late(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let slot = global_id.x;
+    if (slot >= atomicLoad(&state.recheck_count)) {
+        return;
+    }
+
+    let index = recheck_spheres[slot];
+    let sphere = spheres[index];
+    if (is_occluded(sphere.position, sphere.radius)) {
+        atomicAdd(&state.occlusion_culled, 1u);
         return;
     }
-    
-    // Implement frustum culling here
-    // Update draw commands and visible_spheres
 
-    // Your compute shader logic here
-    // Example: Update sphere positions
-    var sphere = spheres[index];
-    // ... update logic ...
-    spheres[index] = sphere;
-}
\ No newline at end of file
+    emit(index, sphere, 1u);
+}
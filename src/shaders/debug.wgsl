@@ -0,0 +1,111 @@
+// Debug views, see DebugView in debug.rs. Concatenated after impostor.wgsl,
+// whose bindings and ray cast it reuses. The debug pipelines bind their own
+// group in place of the shadow group, which nothing here reads.
+
+struct DebugParams {
+    view: u32,
+    // Camera clip planes, for the linear depth view
+    near: f32,
+    far: f32,
+    padding: u32,
+};
+
+// The draw command being drawn, bound with a dynamic offset per draw
+struct DrawInfo {
+    list: u32,
+    bucket: u32,
+};
+
+@group(3) @binding(4) var<uniform> debug: DebugParams;
+@group(3) @binding(5) var<uniform> draw_info: DrawInfo;
+
+// Must match DebugView::shader_index in debug.rs
+const VIEW_NORMALS: u32 = 1u;
+const VIEW_WORLD_POSITION: u32 = 2u;
+const VIEW_MATERIAL_INDEX: u32 = 3u;
+const VIEW_LINEAR_DEPTH: u32 = 4u;
+const VIEW_OVERDRAW: u32 = 5u;
+const VIEW_LOD_LEVEL: u32 = 6u;
+const VIEW_CULLING: u32 = 7u;
+const VIEW_LIGHT_COUNT: u32 = 8u;
+
+// World units per repeat of the world position view
+const POSITION_PERIOD: f32 = 10.0;
+
+// lowbias32 by Chris Wellons
+fn hash(value: u32) -> u32 {
+    var x = value;
+    x ^= x >> 16u;
+    x *= 0x7feb352du;
+    x ^= x >> 15u;
+    x *= 0x846ca68bu;
+    x ^= x >> 16u;
+    return x;
+}
+
+fn debug_color(world_pos: vec3<f32>, normal: vec3<f32>, material_index: u32) -> vec4<f32> {
+    var color = vec3<f32>(0.0);
+    switch (debug.view) {
+        case VIEW_NORMALS: {
+            color = normalize(normal) * 0.5 + 0.5;
+        }
+        case VIEW_WORLD_POSITION: {
+            color = fract(world_pos / POSITION_PERIOD);
+        }
+        case VIEW_MATERIAL_INDEX: {
+            // Offset since zero hashes to black
+            let h = hash(material_index + 1u);
+            color = vec3<f32>(vec3<u32>(h, h >> 8u, h >> 16u) & vec3<u32>(0xffu)) / 255.0;
+        }
+        case VIEW_LINEAR_DEPTH: {
+            // Clip w is the view depth under a perspective projection
+            let depth = (camera.view_proj * vec4<f32>(world_pos, 1.0)).w;
+            color = vec3<f32>(clamp((depth - debug.near) / (debug.far - debug.near), 0.0, 1.0));
+        }
+        case VIEW_OVERDRAW: {
+            // Summed by additive blending, colored by the resolve
+            color = vec3<f32>(1.0, 0.0, 0.0);
+        }
+        case VIEW_LOD_LEVEL: {
+            // Mesh levels 0 to 3, then impostors
+            var levels = array<vec3<f32>, 5>(
+                vec3<f32>(1.0, 0.2, 0.2), vec3<f32>(1.0, 0.9, 0.2), vec3<f32>(0.2, 1.0, 0.2),
+                vec3<f32>(0.2, 0.9, 1.0), vec3<f32>(1.0, 0.2, 1.0),
+            );
+            color = levels[min(draw_info.bucket, 4u)];
+        }
+        case VIEW_CULLING: {
+            // Early, late and transparent lists
+            var lists = array<vec3<f32>, 3>(
+                vec3<f32>(0.2, 1.0, 0.2), vec3<f32>(1.0, 0.5, 0.1), vec3<f32>(0.2, 0.4, 1.0),
+            );
+            color = lists[min(draw_info.list, 2u)];
+        }
+        case VIEW_LIGHT_COUNT: {
+            // Plus one so empty clusters differ from the background, colored
+            // by the resolve
+            color = vec3<f32>(f32(light_clusters[light_cluster(world_pos)]) + 1.0, 0.0, 0.0);
+        }
+        default: {}
+    }
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn fs_debug(in: VertexOutput) -> @location(0) vec4<f32> {
+    return debug_color(in.world_pos, in.normal, in.material_index);
+}
+
+struct ImpostorDebug {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+@fragment
+fn fs_impostor_debug(in: ImpostorOutput) -> ImpostorDebug {
+    let hit = ray_cast_impostor(in, false);
+    var out: ImpostorDebug;
+    out.color = debug_color(hit.position, hit.normal, in.material_index);
+    out.depth = hit.depth;
+    return out;
+}
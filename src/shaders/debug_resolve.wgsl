@@ -0,0 +1,44 @@
+// Copies a debug view to the output target. Concatenated after srgb.wgsl.
+// The overdraw and light count views arrive as a count in red, which is
+// zero for the background, and leave as a heat map.
+
+struct Params {
+    // Count shown in full red, or zero to copy the colors as drawn
+    heat_max: f32,
+    encode_srgb: u32,
+    padding: vec2<u32>,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var input_texture: texture_2d<f32>;
+
+struct FullscreenOutput {
+    @builtin(position) position: vec4<f32>,
+};
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> FullscreenOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: FullscreenOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+// Blue, cyan, green, yellow, red as t goes from 0 to 1
+fn heat(t: f32) -> vec3<f32> {
+    let x = clamp(t, 0.0, 1.0) * 4.0;
+    return clamp(vec3<f32>(x - 2.0, min(x, 4.0 - x), 2.0 - x), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_resolve(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    var color = textureLoad(input_texture, vec2<i32>(in.position.xy), 0).rgb;
+    if (params.heat_max > 0.0) {
+        let count = color.r;
+        color = select(heat((count - 1.0) / (params.heat_max - 1.0)), vec3<f32>(0.0), count < 0.5);
+    }
+    if (params.encode_srgb != 0u) {
+        color = srgb_encode(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)));
+    }
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,22 @@
+// Resolves the multisampled scene depth into the single-sample depth buffer
+// read by the depth pyramid and SSAO. Takes the farthest sample so the
+// pyramid stays conservative along silhouettes.
+
+// Bound as unfilterable float, since GLSL has no texelFetch for depth textures
+@group(0) @binding(0) var depth: texture_multisampled_2d<f32>;
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_resolve(@builtin(position) position: vec4<f32>) -> @builtin(frag_depth) f32 {
+    let coord = vec2<i32>(position.xy);
+    var farthest = 0.0;
+    for (var i = 0; i < i32(textureNumSamples(depth)); i++) {
+        farthest = max(farthest, textureLoad(depth, coord, i).r);
+    }
+    return farthest;
+}
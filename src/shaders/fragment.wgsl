@@ -1,40 +1,346 @@
+struct CameraUniform {
+    // Jittered while temporal antialiasing is on
+    view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+    // Last frame's unjittered view_proj, for the velocity pass
+    previous_view_proj: mat4x4<f32>,
+    // This frame's jitter as an NDC offset in xy
+    jitter: vec4<f32>,
+    // Near and far depth of the light cluster slices in xy
+    clip_planes: vec4<f32>,
+};
+
+// Emission is a plain array so the struct packs to 48 bytes like the Rust side
 struct Material {
     base_color: vec4<f32>,
     metallic_roughness: vec2<f32>,
-    emission: vec3<f32>,
+    emission: array<f32, 3>,
+    padding: array<f32, 3>,
 };
 
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-    position: vec4<f32>,
+// See light.rs for how the enum variants are packed
+struct Light {
+    position: vec3<f32>,
+    range: f32,
+    direction: vec3<f32>,
+    kind: u32,
+    color: vec3<f32>,
+    intensity: f32,
+    angle_scale: f32,
+    angle_offset: f32,
+    shadow: u32,
+    padding: u32,
+};
+
+struct Lights {
+    count: u32,
+    padding: array<u32, 3>,
+    items: array<Light>,
+};
+
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_DIRECTIONAL: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+// Light.shadow values besides a cube map index, see GpuLight in light.rs
+const NO_SHADOW: u32 = 0xffffffffu;
+const CASCADED_SHADOW: u32 = 0xfffffffeu;
+
+// Must match shadow.rs
+const MAX_CASCADES: u32 = 4u;
+const MAX_POINT_SHADOWS: u32 = 4u;
+
+struct Shadows {
+    cascade_view_proj: array<mat4x4<f32>, 4>,
+    // View depth where each cascade ends
+    splits: vec4<f32>,
+    // World size of one texel of each cascade
+    texel_sizes: vec4<f32>,
+    camera_forward: vec4<f32>,
+    cascade_count: u32,
+    depth_bias: f32,
+    normal_bias: f32,
+    show_cascades: u32,
+    // Cube maps store distance to the light times this
+    point_inv_ranges: vec4<f32>,
+    // World size of a cube map texel per unit of distance from the light
+    cube_texel_scale: f32,
+    padding0: f32,
+    padding1: f32,
+    padding2: f32,
 };
 
 @group(0) @binding(0) var<uniform> camera: CameraUniform;
-@group(1) @binding(0) var<storage> materials: array<Material>;
-@group(1) @binding(1) var<uniform> light: vec4<f32>;
+@group(1) @binding(1) var<storage, read> lights: Lights;
+@group(1) @binding(3) var<storage, read> materials: array<Material>;
+
+// One bit per sphere index, see selection.rs
+struct Selection {
+    // Blended over the shading of selected spheres by its alpha
+    tint: vec4<f32>,
+    words: array<u32>,
+};
+
+@group(1) @binding(6) var<storage, read> selection: Selection;
+
+// Must match cluster.rs
+const CLUSTER_DIMS: vec3<u32> = vec3<u32>(16u, 9u, 24u);
+const CLUSTER_STRIDE: u32 = 128u;
+
+// Per cluster a light count followed by that many light indices, see
+// cluster.wgsl
+@group(1) @binding(7) var<storage, read> light_clusters: array<u32>;
+
+// Baked image-based lighting, see environment.rs
+@group(2) @binding(0) var irradiance_map: texture_cube<f32>;
+@group(2) @binding(1) var prefiltered_map: texture_cube<f32>;
+@group(2) @binding(2) var brdf_lut: texture_2d<f32>;
+@group(2) @binding(3) var environment_sampler: sampler;
+
+// Must match PREFILTERED_LEVELS in environment.rs
+const PREFILTERED_LEVELS: f32 = 5.0;
+
+@group(3) @binding(0) var<uniform> shadows: Shadows;
+@group(3) @binding(1) var cascade_maps: texture_depth_2d_array;
+@group(3) @binding(2) var shadow_sampler: sampler_comparison;
+@group(3) @binding(3) var point_maps: texture_depth_cube_array;
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) world_pos: vec3<f32>,
     @location(1) normal: vec3<f32>,
-    @location(2) material: vec3<f32>,
+    @location(2) @interpolate(flat) material_index: u32,
+    @location(3) @interpolate(flat) occlusion: vec4<f32>,
+    @location(4) @interpolate(flat) sphere_index: u32,
 };
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let N = normalize(in.normal);
-    let V = normalize(camera.position.xyz - in.world_pos);
-    let L = normalize(light.xyz - in.world_pos);
+// glTF 2.0 metallic-roughness BRDF for a single light, times N.L
+fn brdf(N: vec3<f32>, V: vec3<f32>, L: vec3<f32>, base_color: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32> {
     let H = normalize(V + L);
+    let n_dot_l = clamp(dot(N, L), 0.0, 1.0);
+    let n_dot_v = clamp(dot(N, V), 1e-4, 1.0);
+    let n_dot_h = clamp(dot(N, H), 0.0, 1.0);
+    let v_dot_h = clamp(dot(V, H), 0.0, 1.0);
+    let alpha = roughness * roughness;
+
+    let f0 = mix(vec3<f32>(0.04), base_color, metallic);
+    let c_diff = mix(base_color, vec3<f32>(0.0), metallic);
+    let F = fresnel_schlick(f0, v_dot_h);
+
+    let diffuse = (vec3<f32>(1.0) - F) * c_diff / PI;
+    let specular = F * distribution_ggx(n_dot_h, alpha) * visibility_smith_ggx(n_dot_l, n_dot_v, alpha);
+    return (diffuse + specular) * n_dot_l;
+}
+
+// Direction towards the light and its radiance arriving at world_pos
+struct LightSample {
+    direction: vec3<f32>,
+    radiance: vec3<f32>,
+};
+
+fn sample_light(light: Light, world_pos: vec3<f32>) -> LightSample {
+    var out: LightSample;
+    if (light.kind == LIGHT_DIRECTIONAL) {
+        out.direction = -light.direction;
+        out.radiance = light.color * light.intensity;
+        return out;
+    }
 
-    let metallic = in.material.x;
-    let roughness = in.material.y;
-    let selected = in.material.z;
-
-    // Implement PBR lighting calculation here
-    let ndotl = max(dot(N, L), 0.0);
-    
-    // Simple lighting for now
-    let color = vec3<f32>(1.0, 0.0, 0.0) * ndotl;
-    return vec4<f32>(color, 1.0);
-}
\ No newline at end of file
+    let to_light = light.position - world_pos;
+    let distance_sq = max(dot(to_light, to_light), 1e-8);
+    out.direction = to_light * inverseSqrt(distance_sq);
+
+    // Inverse-square falloff, windowed to zero at the range
+    var attenuation = 1.0 / distance_sq;
+    if (light.range > 0.0) {
+        let ratio = distance_sq / (light.range * light.range);
+        attenuation *= clamp(1.0 - ratio * ratio, 0.0, 1.0);
+    }
+    if (light.kind == LIGHT_SPOT) {
+        let cone = clamp(dot(light.direction, -out.direction) * light.angle_scale + light.angle_offset, 0.0, 1.0);
+        attenuation *= cone * cone;
+    }
+    out.radiance = light.color * light.intensity * attenuation;
+    return out;
+}
+
+// First cascade that reaches past world_pos, or cascade_count if none does
+fn cascade_index(world_pos: vec3<f32>) -> u32 {
+    let depth = dot(world_pos - camera.position.xyz, shadows.camera_forward.xyz);
+    for (var i = 0u; i < shadows.cascade_count; i++) {
+        if (depth < shadows.splits[i]) {
+            return i;
+        }
+    }
+    return shadows.cascade_count;
+}
+
+// Fraction of the sun visible from world_pos, 3x3 PCF
+fn cascaded_shadow(world_pos: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let cascade = cascade_index(world_pos);
+    if (cascade >= shadows.cascade_count) {
+        return 1.0;
+    }
+
+    let offset_pos = world_pos + normal * shadows.normal_bias * shadows.texel_sizes[cascade];
+    let clip = shadows.cascade_view_proj[cascade] * vec4<f32>(offset_pos, 1.0);
+    let uv = clip.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    let depth = clip.z - shadows.depth_bias;
+    if (depth >= 1.0) {
+        return 1.0;
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(cascade_maps));
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let tap = uv + vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(cascade_maps, shadow_sampler, tap, cascade, depth);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Fraction of a point light visible from world_pos, compared against the
+// distance stored in its cube map
+fn point_shadow(light: Light, world_pos: vec3<f32>, normal: vec3<f32>) -> f32 {
+    let texel_size = distance(world_pos, light.position) * shadows.cube_texel_scale;
+    let from_light = world_pos + normal * shadows.normal_bias * texel_size - light.position;
+    let depth = length(from_light) * shadows.point_inv_ranges[light.shadow] - shadows.depth_bias;
+    if (depth >= 1.0) {
+        return 1.0;
+    }
+    return textureSampleCompareLevel(point_maps, shadow_sampler, from_light, light.shadow, depth);
+}
+
+// Split-sum environment lighting with the multiple-scattering energy
+// compensation used by the glTF sample viewer.
+fn ambient(N: vec3<f32>, V: vec3<f32>, base_color: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32> {
+    let n_dot_v = clamp(dot(N, V), 1e-4, 1.0);
+    let R = reflect(-V, N);
+
+    let f0 = mix(vec3<f32>(0.04), base_color, metallic);
+    let c_diff = mix(base_color, vec3<f32>(0.0), metallic);
+
+    let f_ab = textureSampleLevel(brdf_lut, environment_sampler, vec2<f32>(n_dot_v, roughness), 0.0).rg;
+    let fr = max(vec3<f32>(1.0 - roughness), f0) - f0;
+    let k_s = f0 + fr * pow(1.0 - n_dot_v, 5.0);
+    let fss_ess = k_s * f_ab.x + f_ab.y;
+
+    let ems = 1.0 - (f_ab.x + f_ab.y);
+    let f_avg = f0 + (vec3<f32>(1.0) - f0) / 21.0;
+    let fms_ems = ems * fss_ess * f_avg / (vec3<f32>(1.0) - f_avg * ems);
+    let k_d = c_diff * (vec3<f32>(1.0) - fss_ess - fms_ems);
+
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, N, 0.0).rgb;
+    let radiance = textureSampleLevel(prefiltered_map, environment_sampler, R, roughness * (PREFILTERED_LEVELS - 1.0)).rgb;
+    return (fms_ems + k_d) * irradiance + fss_ess * radiance;
+}
+
+// Offset in light_clusters of the cluster world_pos falls in
+fn light_cluster(world_pos: vec3<f32>) -> u32 {
+    let clip = camera.view_proj * vec4<f32>(world_pos, 1.0);
+    let dims = vec3<f32>(CLUSTER_DIMS);
+    let tile = clamp((clip.xy / clip.w * 0.5 + 0.5) * dims.xy, vec2<f32>(0.0), dims.xy - 1.0);
+    // Clip w is the view depth under a perspective projection
+    let near = camera.clip_planes.x;
+    let far = camera.clip_planes.y;
+    let slice = clamp(log(max(clip.w, near) / near) / log(far / near) * dims.z, 0.0, dims.z - 1.0);
+    let coords = vec3<u32>(vec3<f32>(tile, slice));
+    return ((coords.z * CLUSTER_DIMS.y + coords.y) * CLUSTER_DIMS.x + coords.x) * CLUSTER_STRIDE;
+}
+
+// A shaded surface point, with the image-based ambient part of `color` also
+// on its own so screen-space ambient occlusion can darken just that
+struct Shading {
+    color: vec4<f32>,
+    ambient: vec3<f32>,
+};
+
+// Shared by the mesh and impostor pipelines
+// `occlusion` is the sphere's L0 + L1 neighbour occlusion, which darkens the
+// ambient light only
+fn shade_surface(world_pos: vec3<f32>, normal: vec3<f32>, material_index: u32, occlusion: vec4<f32>) -> Shading {
+    let material = materials[material_index];
+    let base_color = material.base_color.rgb;
+    let metallic = clamp(material.metallic_roughness.x, 0.0, 1.0);
+    let roughness = clamp(material.metallic_roughness.y, 0.0, 1.0);
+    let emission = vec3<f32>(material.emission[0], material.emission[1], material.emission[2]);
+
+    let N = normalize(normal);
+    let V = normalize(camera.position.xyz - world_pos);
+
+    var color = vec3<f32>(0.0);
+    let cluster = light_cluster(world_pos);
+    let light_count = light_clusters[cluster];
+    for (var i = 0u; i < light_count; i++) {
+        let item = lights.items[light_clusters[cluster + 1u + i]];
+        let light = sample_light(item, world_pos);
+        var radiance = light.radiance;
+        if (item.shadow == CASCADED_SHADOW) {
+            radiance *= cascaded_shadow(world_pos, N);
+        } else if (item.shadow < MAX_POINT_SHADOWS) {
+            radiance *= point_shadow(item, world_pos, N);
+        }
+        color += brdf(N, V, light.direction, base_color, metallic, roughness) * radiance;
+    }
+    let ambient_occlusion = 1.0 - clamp(occlusion.w * 0.25 + dot(N, occlusion.xyz) * 0.5, 0.0, 1.0);
+    var ambient_light = ambient(N, V, base_color, metallic, roughness) * ambient_occlusion;
+    color += emission;
+
+    if (shadows.show_cascades != 0u) {
+        var tints = array<vec3<f32>, 5>(
+            vec3<f32>(1.0, 0.3, 0.3), vec3<f32>(0.3, 1.0, 0.3), vec3<f32>(0.3, 0.3, 1.0),
+            vec3<f32>(1.0, 1.0, 0.3), vec3<f32>(1.0, 1.0, 1.0),
+        );
+        let tint = tints[min(cascade_index(world_pos), MAX_CASCADES)];
+        color *= tint;
+        ambient_light *= tint;
+    }
+
+    var out: Shading;
+    out.color = vec4<f32>(color + ambient_light, material.base_color.a);
+    out.ambient = ambient_light;
+    return out;
+}
+
+fn shade(world_pos: vec3<f32>, normal: vec3<f32>, material_index: u32, occlusion: vec4<f32>) -> vec4<f32> {
+    return shade_surface(world_pos, normal, material_index, occlusion).color;
+}
+
+fn is_selected(sphere_index: u32) -> bool {
+    return (selection.words[sphere_index / 32u] & (1u << (sphere_index % 32u))) != 0u;
+}
+
+fn tint_selected(color: vec4<f32>, sphere_index: u32) -> vec4<f32> {
+    if (selection.tint.a <= 0.0 || !is_selected(sphere_index)) {
+        return color;
+    }
+    return vec4<f32>(mix(color.rgb, selection.tint.rgb, selection.tint.a), color.a);
+}
+
+// The part of `ambient` left in the color tint_selected returns
+fn tint_selected_ambient(ambient: vec3<f32>, sphere_index: u32) -> vec3<f32> {
+    if (selection.tint.a <= 0.0 || !is_selected(sphere_index)) {
+        return ambient;
+    }
+    return ambient * (1.0 - selection.tint.a);
+}
+
+// The ambient output goes to a second target while screen-space ambient
+// occlusion is on, and is dropped by pipelines without one
+struct SphereFragment {
+    @location(0) color: vec4<f32>,
+    @location(1) ambient: vec4<f32>,
+};
+
+fn sphere_fragment(shading: Shading, sphere_index: u32) -> SphereFragment {
+    var out: SphereFragment;
+    out.color = tint_selected(shading.color, sphere_index);
+    out.ambient = vec4<f32>(tint_selected_ambient(shading.ambient, sphere_index), out.color.a);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> SphereFragment {
+    return sphere_fragment(shade_surface(in.world_pos, in.normal, in.material_index, in.occlusion), in.sphere_index);
+}
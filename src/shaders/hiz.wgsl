@@ -0,0 +1,60 @@
+// Max-depth pyramid for occlusion culling, stored level after level in one
+// storage buffer. Level 0 is the depth buffer reduced to the next power of
+// two below its size; each further level halves that, so uv -> texel mapping
+// is exact at every level.
+
+struct LevelParams {
+    src_offset: u32,
+    src_width: u32,
+    src_height: u32,
+    dst_offset: u32,
+    dst_width: u32,
+    dst_height: u32,
+};
+
+// Depth32Float bound as unfilterable float, which every backend can texelFetch
+@group(0) @binding(0) var depth: texture_2d<f32>;
+@group(0) @binding(1) var<storage, read_write> pyramid: array<f32>;
+@group(0) @binding(2) var<uniform> level: LevelParams;
+
+@compute @workgroup_size(8, 8)
+fn init(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dst_size = vec2<u32>(level.dst_width, level.dst_height);
+    if (any(global_id.xy >= dst_size)) {
+        return;
+    }
+
+    // Each pyramid texel covers a non-integer footprint of up to 3x3 depth texels
+    let src_size = textureDimensions(depth);
+    let ratio = vec2<f32>(src_size) / vec2<f32>(dst_size);
+    let start = vec2<u32>(floor(vec2<f32>(global_id.xy) * ratio));
+    let end = min(vec2<u32>(ceil(vec2<f32>(global_id.xy + 1u) * ratio)), src_size);
+
+    var farthest = 0.0;
+    for (var y = start.y; y < end.y; y++) {
+        for (var x = start.x; x < end.x; x++) {
+            farthest = max(farthest, textureLoad(depth, vec2<u32>(x, y), 0).r);
+        }
+    }
+    pyramid[level.dst_offset + global_id.y * level.dst_width + global_id.x] = farthest;
+}
+
+fn load_source(coord: vec2<u32>) -> f32 {
+    // Non-square pyramids bottom out at 1 texel on one axis first
+    let clamped = min(coord, vec2<u32>(level.src_width, level.src_height) - 1u);
+    return pyramid[level.src_offset + clamped.y * level.src_width + clamped.x];
+}
+
+@compute @workgroup_size(8, 8)
+fn downsample(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if (global_id.x >= level.dst_width || global_id.y >= level.dst_height) {
+        return;
+    }
+
+    let base = global_id.xy * 2u;
+    let farthest = max(
+        max(load_source(base), load_source(base + vec2<u32>(1u, 0u))),
+        max(load_source(base + vec2<u32>(0u, 1u)), load_source(base + 1u)),
+    );
+    pyramid[level.dst_offset + global_id.y * level.dst_width + global_id.x] = farthest;
+}
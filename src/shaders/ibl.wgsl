@@ -0,0 +1,153 @@
+// Environment baking passes. Concatenated after brdf.wgsl. Every pass draws
+// a fullscreen triangle into one face (and mip) of its target.
+
+struct BakeParams {
+    face: u32,
+    roughness: f32,
+    source_level: f32,
+    target_size: f32,
+};
+
+@group(0) @binding(0) var<uniform> params: BakeParams;
+@group(0) @binding(1) var bake_sampler: sampler;
+@group(0) @binding(2) var equirect: texture_2d<f32>;
+@group(0) @binding(3) var environment: texture_cube<f32>;
+
+const SAMPLE_COUNT: u32 = 256u;
+const LUT_SAMPLE_COUNT: u32 = 512u;
+// Must match BRDF_LUT_SIZE in environment.rs
+const BRDF_LUT_SIZE: f32 = 128.0;
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Direction through a texel of the current face, in the usual +X, -X, +Y,
+// -Y, +Z, -Z layer order
+fn face_direction(position: vec2<f32>) -> vec3<f32> {
+    let uv = position / params.target_size * 2.0 - 1.0;
+    switch params.face {
+        case 0u: { return normalize(vec3<f32>(1.0, -uv.y, -uv.x)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -uv.y, uv.x)); }
+        case 2u: { return normalize(vec3<f32>(uv.x, 1.0, uv.y)); }
+        case 3u: { return normalize(vec3<f32>(uv.x, -1.0, -uv.y)); }
+        case 4u: { return normalize(vec3<f32>(uv.x, -uv.y, 1.0)); }
+        default: { return normalize(vec3<f32>(-uv.x, -uv.y, -1.0)); }
+    }
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+fn tangent_to_world(v: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    var up = vec3<f32>(0.0, 0.0, 1.0);
+    if (abs(n.z) > 0.999) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, n));
+    let bitangent = cross(n, tangent);
+    return tangent * v.x + bitangent * v.y + n * v.z;
+}
+
+// GGX-distributed half vector around +Z
+fn importance_sample_ggx(xi: vec2<f32>, alpha: f32) -> vec3<f32> {
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
+
+// Cosine-weighted direction around +Z
+fn sample_cosine(xi: vec2<f32>) -> vec3<f32> {
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt(1.0 - xi.y);
+    let sin_theta = sqrt(xi.y);
+    return vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
+
+// Filtered importance sampling: read the environment mip whose texels cover
+// roughly the solid angle of one sample, which keeps bright spots from
+// turning into fireflies.
+fn environment_level(pdf: f32, sample_count: u32) -> f32 {
+    let size = f32(textureDimensions(environment).x);
+    let texel_solid_angle = 4.0 * PI / (6.0 * size * size);
+    let sample_solid_angle = 1.0 / (f32(sample_count) * pdf + 1e-4);
+    return max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+}
+
+@fragment
+fn fs_equirect_to_cube(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let dir = face_direction(position.xy);
+    let uv = vec2<f32>(atan2(dir.z, dir.x) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+    return vec4<f32>(textureSampleLevel(equirect, bake_sampler, uv, params.source_level).rgb, 1.0);
+}
+
+// Cosine-weighted average radiance, i.e. irradiance / PI
+@fragment
+fn fs_irradiance(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let n = face_direction(position.xy);
+    var sum = vec3<f32>(0.0);
+    for (var i = 0u; i < SAMPLE_COUNT; i++) {
+        let local = sample_cosine(hammersley(i, SAMPLE_COUNT));
+        let l = tangent_to_world(local, n);
+        let level = environment_level(local.z / PI, SAMPLE_COUNT);
+        sum += textureSampleLevel(environment, bake_sampler, l, level).rgb;
+    }
+    return vec4<f32>(sum / f32(SAMPLE_COUNT), 1.0);
+}
+
+// GGX-prefiltered radiance for one roughness, assuming N = V = R
+@fragment
+fn fs_prefilter(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let n = face_direction(position.xy);
+    if (params.roughness == 0.0) {
+        return vec4<f32>(textureSampleLevel(environment, bake_sampler, n, 0.0).rgb, 1.0);
+    }
+
+    let alpha = params.roughness * params.roughness;
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i++) {
+        let h = tangent_to_world(importance_sample_ggx(hammersley(i, SAMPLE_COUNT), alpha), n);
+        let l = 2.0 * dot(n, h) * h - n;
+        let n_dot_l = dot(n, l);
+        if (n_dot_l > 0.0) {
+            // pdf = D * N.H / (4 V.H), and V.H == N.H here
+            let pdf = distribution_ggx(clamp(dot(n, h), 0.0, 1.0), alpha) / 4.0;
+            let level = environment_level(pdf, SAMPLE_COUNT);
+            sum += textureSampleLevel(environment, bake_sampler, l, level).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(sum / max(weight, 1e-4), 1.0);
+}
+
+// Split-sum scale and bias applied to F0, indexed by (N.V, roughness)
+@fragment
+fn fs_brdf_lut(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let uv = position.xy / BRDF_LUT_SIZE;
+    let n_dot_v = uv.x;
+    let alpha = uv.y * uv.y;
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < LUT_SAMPLE_COUNT; i++) {
+        let h = importance_sample_ggx(hammersley(i, LUT_SAMPLE_COUNT), alpha);
+        let l = 2.0 * dot(v, h) * h - v;
+        let n_dot_l = l.z;
+        let n_dot_h = h.z;
+        let v_dot_h = clamp(dot(v, h), 0.0, 1.0);
+        if (n_dot_l > 0.0) {
+            let vis = visibility_smith_ggx(n_dot_l, n_dot_v, alpha);
+            let g_vis = 4.0 * vis * n_dot_l * v_dot_h / n_dot_h;
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+    return vec4<f32>(scale, bias, 0.0, 1.0) / vec4<f32>(f32(LUT_SAMPLE_COUNT), f32(LUT_SAMPLE_COUNT), 1.0, 1.0);
+}
@@ -0,0 +1,146 @@
+// Ray-cast sphere impostors. Concatenated after fragment.wgsl, which
+// provides the camera/light bindings and `shade`.
+
+struct Sphere {
+    position: vec3<f32>,
+    radius: f32,
+    material_index: u32,
+    padding: array<u32, 3>,
+};
+
+@group(1) @binding(0) var<storage, read> spheres: array<Sphere>;
+@group(1) @binding(2) var<storage, read> visible_spheres: array<u32>;
+@group(1) @binding(4) var<storage, read> sphere_occlusion: array<vec4<f32>>;
+
+struct ImpostorOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_pos: vec3<f32>,
+    @location(1) @interpolate(flat) center: vec3<f32>,
+    @location(2) @interpolate(flat) radius: f32,
+    @location(3) @interpolate(flat) material_index: u32,
+    @location(4) @interpolate(flat) occlusion: vec4<f32>,
+    @location(5) @interpolate(flat) sphere_index: u32,
+};
+
+// Like SphereFragment, plus the ray-cast depth
+struct ImpostorFragment {
+    @location(0) color: vec4<f32>,
+    @location(1) ambient: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+@vertex
+fn vs_impostor(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> ImpostorOutput {
+    return impostor_vertex(vertex_index, instance_index);
+}
+
+// Body of vs_impostor, shared with the vertex stages of other passes
+fn impostor_vertex(vertex_index: u32, instance_index: u32) -> ImpostorOutput {
+    let sphere_index = visible_spheres[instance_index];
+    let sphere = spheres[sphere_index];
+
+    var out: ImpostorOutput;
+    out.center = sphere.position;
+    out.radius = sphere.radius;
+    out.material_index = sphere.material_index;
+    out.occlusion = sphere_occlusion[sphere_index];
+    out.sphere_index = sphere_index;
+
+    let to_camera = camera.position.xyz - sphere.position;
+    let distance_sq = dot(to_camera, to_camera);
+    let radius_sq = sphere.radius * sphere.radius;
+    if (distance_sq <= radius_sq) {
+        // Camera is inside the sphere; there is no outer surface to see
+        out.position = vec4<f32>(0.0, 0.0, 2.0, 1.0);
+        out.world_pos = sphere.position;
+        return out;
+    }
+
+    // Quad through the center, facing the camera, sized to enclose the
+    // perspective silhouette (the tangent cone) rather than just the radius.
+    let forward = to_camera * inverseSqrt(distance_sq);
+    var up_hint = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(forward.y) > 0.99) {
+        up_hint = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let right = normalize(cross(up_hint, forward));
+    let up = cross(forward, right);
+    let extent = sphere.radius * sqrt(distance_sq / (distance_sq - radius_sq));
+
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+    let world_position = sphere.position + (right * corner.x + up * corner.y) * extent;
+
+    out.position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.world_pos = world_position;
+    return out;
+}
+
+// Surface point a pixel's ray hits, with its clip-space depth
+struct ImpostorHit {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    depth: f32,
+    // Fraction of the pixel inside the silhouette
+    coverage: f32,
+};
+
+// Discards pixels whose ray misses the sphere. With `edge_coverage` pixels
+// partly inside the silhouette are kept for alpha-to-coverage under MSAA; the
+// ray's miss distance is a signed distance to the edge, so fwidth gives it in
+// pixels
+fn ray_cast_impostor(in: ImpostorOutput, edge_coverage: bool) -> ImpostorHit {
+    let origin = camera.position.xyz;
+    let dir = normalize(in.world_pos - origin);
+
+    let oc = origin - in.center;
+    let b = dot(oc, dir);
+    let c = dot(oc, oc) - in.radius * in.radius;
+    let h = b * b - c;
+
+    let miss = sqrt(max(dot(oc, oc) - b * b, 0.0));
+    let coverage = clamp(0.5 + (in.radius - miss) / max(fwidth(miss), 1e-6), 0.0, 1.0);
+    if (h < 0.0 && !(edge_coverage && coverage > 0.0)) {
+        discard;
+    }
+
+    // Rays just outside the silhouette shade the point closest to it
+    var out: ImpostorHit;
+    out.position = origin + dir * (-b - sqrt(max(h, 0.0)));
+    out.normal = normalize(out.position - in.center);
+    let clip = camera.view_proj * vec4<f32>(out.position, 1.0);
+    out.depth = clip.z / clip.w;
+    out.coverage = coverage;
+    return out;
+}
+
+fn impostor_fragment(in: ImpostorOutput, edge_coverage: bool) -> ImpostorFragment {
+    let hit = ray_cast_impostor(in, edge_coverage);
+
+    let shaded = sphere_fragment(shade_surface(hit.position, hit.normal, in.material_index, in.occlusion), in.sphere_index);
+
+    var out: ImpostorFragment;
+    out.color = shaded.color;
+    out.ambient = shaded.ambient;
+    if (edge_coverage) {
+        out.color.a = hit.coverage;
+    }
+    out.depth = hit.depth;
+    return out;
+}
+
+@fragment
+fn fs_impostor(in: ImpostorOutput) -> ImpostorFragment {
+    return impostor_fragment(in, false);
+}
+
+@fragment
+fn fs_impostor_multisampled(in: ImpostorOutput) -> ImpostorFragment {
+    return impostor_fragment(in, true);
+}
@@ -0,0 +1,61 @@
+// Screen-space outline around the selection mask drawn by selection.wgsl.
+// Concatenated after srgb.wgsl. Pixels outside the mask are covered by the
+// outline color as far as `width` pixels from the nearest masked pixel, with
+// a one pixel falloff for antialiasing.
+
+struct Params {
+    // Blended over the input by its alpha
+    color: vec4<f32>,
+    width: f32,
+    encode_srgb: u32,
+    padding: vec2<u32>,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var input_texture: texture_2d<f32>;
+@group(0) @binding(2) var mask_texture: texture_2d<f32>;
+
+struct FullscreenOutput {
+    @builtin(position) position: vec4<f32>,
+};
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> FullscreenOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: FullscreenOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+fn masked(coord: vec2<i32>, size: vec2<i32>) -> bool {
+    return textureLoad(mask_texture, clamp(coord, vec2<i32>(0), size - 1), 0).r > 0.5;
+}
+
+@fragment
+fn fs_outline(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(in.position.xy);
+    let size = vec2<i32>(textureDimensions(mask_texture));
+    var color = textureLoad(input_texture, coord, 0);
+
+    if (!masked(coord, size)) {
+        // Brute-force search of the square around the pixel for the nearest
+        // masked pixel; cheap at the few pixels outlines are wide
+        let radius = i32(ceil(params.width));
+        var nearest_sq = f32(radius * radius * 2 + 1);
+        for (var y = -radius; y <= radius; y++) {
+            for (var x = -radius; x <= radius; x++) {
+                let distance_sq = f32(x * x + y * y);
+                if (distance_sq < nearest_sq && masked(coord + vec2<i32>(x, y), size)) {
+                    nearest_sq = distance_sq;
+                }
+            }
+        }
+        let coverage = clamp(params.width + 0.5 - sqrt(nearest_sq), 0.0, 1.0);
+        color = vec4<f32>(mix(color.rgb, params.color.rgb, coverage * params.color.a), color.a);
+    }
+
+    if (params.encode_srgb != 0u) {
+        color = vec4<f32>(srgb_encode(clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0))), color.a);
+    }
+    return color;
+}
@@ -0,0 +1,22 @@
+// Object IDs for picking. Each sphere writes its index plus one into an
+// R32Uint target, so zero means no sphere. Concatenated after impostor.wgsl,
+// whose bindings and ray cast it reuses.
+
+struct ImpostorPick {
+    @location(0) id: u32,
+    @builtin(frag_depth) depth: f32,
+};
+
+@fragment
+fn fs_pick(in: VertexOutput) -> @location(0) u32 {
+    return in.sphere_index + 1u;
+}
+
+@fragment
+fn fs_impostor_pick(in: ImpostorOutput) -> ImpostorPick {
+    let hit = ray_cast_impostor(in, false);
+    var out: ImpostorPick;
+    out.id = in.sphere_index + 1u;
+    out.depth = hit.depth;
+    return out;
+}
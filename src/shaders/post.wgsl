@@ -0,0 +1,52 @@
+// Prelude of every ShaderEffect, concatenated after srgb.wgsl and before the
+// effect's own source. That source must define
+//
+//     fn effect(in: PostInput) -> vec4<f32>
+//
+// returning the linear color of the pixel `in` describes. The previous
+// step's output is bound as `input_texture`, the scene depth as
+// `depth_texture`.
+
+struct PostParams {
+    // Output size in pixels, and its reciprocal
+    size: vec2<f32>,
+    inverse_size: vec2<f32>,
+    // Counts up by one every frame
+    frame: u32,
+    // Set when this effect writes the final non-sRGB target
+    encode_srgb: u32,
+    padding: vec2<u32>,
+    // Free for the effect, see ShaderEffect::set_params
+    params: array<vec4<f32>, 4>,
+};
+
+@group(0) @binding(0) var<uniform> post: PostParams;
+@group(0) @binding(1) var input_texture: texture_2d<f32>;
+// Linear, clamped to the edges
+@group(0) @binding(2) var input_sampler: sampler;
+// Depth32Float bound as unfilterable float, read it with textureLoad
+@group(0) @binding(3) var depth_texture: texture_2d<f32>;
+
+struct PostInput {
+    @builtin(position) position: vec4<f32>,
+    // 0..1 across the target, origin at the top left
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> PostInput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: PostInput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_effect(in: PostInput) -> @location(0) vec4<f32> {
+    var color = effect(in);
+    if (post.encode_srgb != 0u) {
+        color = vec4<f32>(srgb_encode(clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0))), color.a);
+    }
+    return color;
+}
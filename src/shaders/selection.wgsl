@@ -0,0 +1,55 @@
+// Mask of the visible parts of selected spheres, which the outline pass
+// traces. Concatenated after impostor.wgsl, whose bindings and ray cast it
+// reuses. Unselected spheres are collapsed in the vertex stage, so the pass
+// rasterizes only the selection. Drawn against the finished depth buffer
+// without writing it.
+
+// Behind the far plane, so the primitive is clipped
+const COLLAPSED: vec4<f32> = vec4<f32>(0.0, 0.0, 2.0, 1.0);
+
+@vertex
+fn vs_selection_mask(
+    @location(0) position: vec3<f32>,
+    @builtin(instance_index) instance_index: u32,
+) -> @builtin(position) vec4<f32> {
+    let sphere_index = visible_spheres[instance_index];
+    if (!is_selected(sphere_index)) {
+        return COLLAPSED;
+    }
+    let sphere = spheres[sphere_index];
+
+    // Same expression as vs_main so the depth test passes on equal depth
+    let world_position = sphere.position + position * sphere.radius;
+    return camera.view_proj * vec4<f32>(world_position, 1.0);
+}
+
+@fragment
+fn fs_selection_mask() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0);
+}
+
+@vertex
+fn vs_impostor_selection_mask(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> ImpostorOutput {
+    var out = impostor_vertex(vertex_index, instance_index);
+    if (!is_selected(out.sphere_index)) {
+        out.position = COLLAPSED;
+    }
+    return out;
+}
+
+struct ImpostorMask {
+    @location(0) mask: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+@fragment
+fn fs_impostor_selection_mask(in: ImpostorOutput) -> ImpostorMask {
+    let hit = ray_cast_impostor(in, false);
+    var out: ImpostorMask;
+    out.mask = vec4<f32>(1.0);
+    out.depth = hit.depth;
+    return out;
+}
@@ -0,0 +1,134 @@
+// Depth-only shadow caster passes, drawing the buckets filled by the
+// culling shader for a shadow view.
+
+struct Sphere {
+    position: vec3<f32>,
+    radius: f32,
+    material_index: u32,
+    padding: array<u32, 3>,
+};
+
+// For cascades `origin` is the direction the light travels with w = 0. For
+// cube faces it is the light position with w = 1 / range, and the casters
+// write their distance to the light times that as depth.
+struct ShadowView {
+    view_proj: mat4x4<f32>,
+    origin: vec4<f32>,
+};
+
+@group(0) @binding(0) var<uniform> view: ShadowView;
+@group(1) @binding(0) var<storage, read> spheres: array<Sphere>;
+@group(1) @binding(2) var<storage, read> visible_spheres: array<u32>;
+
+@vertex
+fn vs_shadow_mesh(
+    @location(0) position: vec3<f32>,
+    @builtin(instance_index) instance_index: u32,
+) -> @builtin(position) vec4<f32> {
+    let sphere = spheres[visible_spheres[instance_index]];
+    return view.view_proj * vec4<f32>(sphere.position + position * sphere.radius, 1.0);
+}
+
+struct CubeMeshOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_pos: vec3<f32>,
+};
+
+@vertex
+fn vs_cube_shadow_mesh(
+    @location(0) position: vec3<f32>,
+    @builtin(instance_index) instance_index: u32,
+) -> CubeMeshOutput {
+    let sphere = spheres[visible_spheres[instance_index]];
+    var out: CubeMeshOutput;
+    out.world_pos = sphere.position + position * sphere.radius;
+    out.position = view.view_proj * vec4<f32>(out.world_pos, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_cube_shadow_mesh(in: CubeMeshOutput) -> @builtin(frag_depth) f32 {
+    return distance(in.world_pos, view.origin.xyz) * view.origin.w;
+}
+
+struct ImpostorOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_pos: vec3<f32>,
+    @location(1) @interpolate(flat) center: vec3<f32>,
+    @location(2) @interpolate(flat) radius: f32,
+};
+
+// Same billboard as vs_impostor, facing the light instead of the camera
+@vertex
+fn vs_shadow_impostor(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> ImpostorOutput {
+    let sphere = spheres[visible_spheres[instance_index]];
+
+    var out: ImpostorOutput;
+    out.center = sphere.position;
+    out.radius = sphere.radius;
+
+    var forward = -view.origin.xyz;
+    var extent = sphere.radius;
+    if (view.origin.w != 0.0) {
+        let to_light = view.origin.xyz - sphere.position;
+        let distance_sq = dot(to_light, to_light);
+        let radius_sq = sphere.radius * sphere.radius;
+        if (distance_sq <= radius_sq) {
+            // The light is inside this sphere, which then can't cast a shadow
+            out.position = vec4<f32>(0.0, 0.0, 2.0, 1.0);
+            out.world_pos = sphere.position;
+            return out;
+        }
+        forward = to_light * inverseSqrt(distance_sq);
+        extent = sphere.radius * sqrt(distance_sq / (distance_sq - radius_sq));
+    }
+
+    var up_hint = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(forward.y) > 0.99) {
+        up_hint = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let right = normalize(cross(up_hint, forward));
+    let up = cross(forward, right);
+
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+    let world_position = sphere.position + (right * corner.x + up * corner.y) * extent;
+
+    out.position = view.view_proj * vec4<f32>(world_position, 1.0);
+    out.world_pos = world_position;
+    return out;
+}
+
+// Distance along `dir` from `origin` to the front of the impostor's sphere
+fn trace_impostor(in: ImpostorOutput, origin: vec3<f32>, dir: vec3<f32>) -> f32 {
+    let oc = origin - in.center;
+    let b = dot(oc, dir);
+    let c = dot(oc, oc) - in.radius * in.radius;
+    let h = b * b - c;
+    if (h < 0.0) {
+        discard;
+    }
+    return -b - sqrt(h);
+}
+
+@fragment
+fn fs_shadow_impostor(in: ImpostorOutput) -> @builtin(frag_depth) f32 {
+    let dir = view.origin.xyz;
+    let origin = in.world_pos - dir * in.radius * 2.0;
+    let hit = origin + dir * trace_impostor(in, origin, dir);
+    let clip = view.view_proj * vec4<f32>(hit, 1.0);
+    return clip.z / clip.w;
+}
+
+@fragment
+fn fs_cube_shadow_impostor(in: ImpostorOutput) -> @builtin(frag_depth) f32 {
+    let origin = view.origin.xyz;
+    let dir = normalize(in.world_pos - origin);
+    return trace_impostor(in, origin, dir) * view.origin.w;
+}
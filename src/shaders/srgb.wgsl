@@ -0,0 +1,7 @@
+// Post-processing passes that may write the final output target encode
+// their result with this when the target format doesn't do it on write.
+fn srgb_encode(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
@@ -0,0 +1,151 @@
+// Screen-space ambient occlusion from the depth buffer alone, so it works
+// for any geometry. The occlusion pass and the blur run at half resolution;
+// the composite pass darkens the frame's image-based ambient light by it.
+
+struct Params {
+    projection: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+    radius: f32,
+    intensity: f32,
+    padding: vec2<f32>,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+// Depth32Float bound as unfilterable float, which every backend can texelFetch
+@group(0) @binding(1) var depth: texture_2d<f32>;
+// Half resolution occlusion read by the blur and composite passes
+@group(0) @binding(2) var occlusion: texture_2d<f32>;
+@group(0) @binding(3) var occlusion_sampler: sampler;
+// Full resolution ambient light of the sphere passes, read by the composite
+@group(1) @binding(0) var ambient_light: texture_2d<f32>;
+
+const SAMPLE_COUNT: u32 = 12u;
+const BLUR_RADIUS: i32 = 4;
+// Depth difference, relative to the center's depth, that halves a blur weight
+const BLUR_DEPTH_FALLOFF: f32 = 0.02;
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn load_depth(coord: vec2<i32>) -> f32 {
+    let clamped = clamp(coord, vec2<i32>(0), vec2<i32>(textureDimensions(depth)) - 1);
+    return textureLoad(depth, clamped, 0).r;
+}
+
+// View-space position of a full resolution depth texel
+fn view_position(coord: vec2<i32>) -> vec3<f32> {
+    let uv = (vec2<f32>(coord) + 0.5) / vec2<f32>(textureDimensions(depth));
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, load_depth(coord), 1.0);
+    let view = params.inverse_projection * ndc;
+    return view.xyz / view.w;
+}
+
+// Of the two neighbours along an axis, the one on the same surface is the
+// one closer in depth; differencing against it keeps edges sharp
+fn closest_difference(center: vec3<f32>, coord: vec2<i32>, step: vec2<i32>) -> vec3<f32> {
+    let forward = view_position(coord + step) - center;
+    let backward = center - view_position(coord - step);
+    if (abs(forward.z) < abs(backward.z)) {
+        return forward;
+    }
+    return backward;
+}
+
+fn interleaved_gradient_noise(position: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(position, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+@fragment
+fn fs_occlusion(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(position.xy) * 2;
+    if (load_depth(coord) >= 1.0) {
+        return vec4<f32>(1.0);
+    }
+
+    let center = view_position(coord);
+    let dx = closest_difference(center, coord, vec2<i32>(1, 0));
+    let dy = closest_difference(center, coord, vec2<i32>(0, 1));
+    // Screen y runs down, so this faces the camera
+    let normal = normalize(cross(dy, dx));
+
+    // Per-pixel rotation of the sample pattern; the blur removes the noise
+    let angle = interleaved_gradient_noise(position.xy) * 6.28318530;
+    var up = vec3<f32>(0.0, 0.0, 1.0);
+    if (abs(normal.z) > 0.999) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent0 = normalize(cross(up, normal));
+    let bitangent0 = cross(normal, tangent0);
+    let tangent = tangent0 * cos(angle) + bitangent0 * sin(angle);
+    let bitangent = cross(normal, tangent);
+
+    let full_size = vec2<f32>(textureDimensions(depth));
+    let bias = 0.03 * params.radius;
+    var occluded = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i++) {
+        // Cosine-distributed hemisphere directions, denser near the center
+        let t = (f32(i) + 0.5) / f32(SAMPLE_COUNT);
+        let phi = f32(i) * 2.39996323;
+        let sin_theta = sqrt(t);
+        let local = vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), sqrt(1.0 - t));
+        let scale = mix(0.1, 1.0, t * t);
+        let sample_pos = center + (tangent * local.x + bitangent * local.y + normal * local.z) * scale * params.radius;
+
+        let clip = params.projection * vec4<f32>(sample_pos, 1.0);
+        let uv = clip.xy / clip.w * vec2<f32>(0.5, -0.5) + 0.5;
+        if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+            continue;
+        }
+        let scene = view_position(vec2<i32>(uv * full_size));
+        // View space looks down -z, so larger z is nearer the camera
+        let range = smoothstep(0.0, 1.0, params.radius / max(abs(center.z - scene.z), 1e-4));
+        occluded += select(0.0, range, scene.z >= sample_pos.z + bias);
+    }
+
+    let ambient = pow(1.0 - occluded / f32(SAMPLE_COUNT), params.intensity);
+    return vec4<f32>(ambient, 0.0, 0.0, 1.0);
+}
+
+// Depth-aware 9-tap blur along one axis
+fn bilateral_blur(position: vec2<f32>, axis: vec2<i32>) -> f32 {
+    let coord = vec2<i32>(position);
+    let size = vec2<i32>(textureDimensions(occlusion));
+    let center_depth = -view_position(coord * 2).z;
+
+    var sum = 0.0;
+    var weight_sum = 0.0;
+    for (var i = -BLUR_RADIUS; i <= BLUR_RADIUS; i++) {
+        let tap = clamp(coord + axis * i, vec2<i32>(0), size - 1);
+        let tap_depth = -view_position(tap * 2).z;
+        let spatial = exp(-f32(i * i) / 8.0);
+        let difference = abs(tap_depth - center_depth) / (center_depth * BLUR_DEPTH_FALLOFF);
+        let weight = spatial / (1.0 + difference * difference);
+        sum += textureLoad(occlusion, tap, 0).r * weight;
+        weight_sum += weight;
+    }
+    return sum / weight_sum;
+}
+
+@fragment
+fn fs_blur_horizontal(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return vec4<f32>(bilateral_blur(position.xy, vec2<i32>(1, 0)), 0.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_blur_vertical(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return vec4<f32>(bilateral_blur(position.xy, vec2<i32>(0, 1)), 0.0, 0.0, 1.0);
+}
+
+// The occluded part of the ambient light, subtracted from the frame by the
+// blend state
+@fragment
+fn fs_composite(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    // Half resolution texels cover two pixels, rounded up on odd sizes
+    let uv = position.xy * 0.5 / vec2<f32>(textureDimensions(occlusion));
+    let visibility = textureSampleLevel(occlusion, occlusion_sampler, uv, 0.0).r;
+    let ambient = textureLoad(ambient_light, vec2<i32>(position.xy), 0).rgb;
+    return vec4<f32>(ambient * (1.0 - visibility), 0.0);
+}
@@ -0,0 +1,129 @@
+// Temporal antialiasing resolve. Each frame is rendered with a different
+// sub-pixel jitter; the resolve reprojects the accumulated history along the
+// velocity buffer, clips it to the current frame's neighbourhood so stale
+// colors don't ghost, and blends the two. A sharpening pass then writes the
+// result back into the scene texture.
+
+struct Params {
+    // Weight of the history in the blend, 0 while there is no valid history
+    history_weight: f32,
+    // Strength of the unsharp mask in the sharpening pass
+    sharpness: f32,
+    padding: vec2<f32>,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var scene: texture_2d<f32>;
+@group(0) @binding(2) var history: texture_2d<f32>;
+@group(0) @binding(3) var velocity: texture_2d<f32>;
+// Depth32Float bound as unfilterable float, see ssao.wgsl
+@group(0) @binding(4) var depth: texture_2d<f32>;
+@group(0) @binding(5) var history_sampler: sampler;
+
+// Width of the neighbourhood color box in standard deviations
+const CLIP_GAMMA: f32 = 1.25;
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn rgb_to_ycocg(color: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        dot(color, vec3<f32>(0.25, 0.5, 0.25)),
+        dot(color, vec3<f32>(0.5, 0.0, -0.5)),
+        dot(color, vec3<f32>(-0.25, 0.5, -0.25)),
+    );
+}
+
+fn ycocg_to_rgb(color: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        color.x + color.y - color.z,
+        color.x + color.z,
+        color.x - color.y - color.z,
+    );
+}
+
+// Scales HDR colors into 0..1 before blending so a single very bright
+// sample can't dominate the average; undone by `unweight`
+fn weight(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color.x);
+}
+
+fn unweight(color: vec3<f32>) -> vec3<f32> {
+    return color / max(1.0 - color.x, 1e-4);
+}
+
+// Moves `color` towards the box center until it lies inside the box
+fn clip_to_box(color: vec3<f32>, center: vec3<f32>, extent: vec3<f32>) -> vec3<f32> {
+    let offset = color - center;
+    let units = abs(offset / max(extent, vec3<f32>(1e-5)));
+    let furthest = max(units.x, max(units.y, units.z));
+    if (furthest > 1.0) {
+        return center + offset / furthest;
+    }
+    return color;
+}
+
+@fragment
+fn fs_resolve(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(scene));
+    let coord = vec2<i32>(position.xy);
+    let current = textureLoad(scene, coord, 0);
+
+    // Color moments of the 3x3 neighbourhood, and the nearest surface in it
+    // whose motion is used, so the edges of moving spheres move with them
+    var moment1 = vec3<f32>(0.0);
+    var moment2 = vec3<f32>(0.0);
+    var nearest_depth = 1.0;
+    var nearest = coord;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let tap = clamp(coord + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            let color = weight(rgb_to_ycocg(max(textureLoad(scene, tap, 0).rgb, vec3<f32>(0.0))));
+            moment1 += color;
+            moment2 += color * color;
+            let tap_depth = textureLoad(depth, tap, 0).r;
+            if (tap_depth < nearest_depth) {
+                nearest_depth = tap_depth;
+                nearest = tap;
+            }
+        }
+    }
+
+    let uv = position.xy / vec2<f32>(size);
+    let history_uv = uv - textureLoad(velocity, nearest, 0).xy;
+    if (params.history_weight <= 0.0 || any(history_uv < vec2<f32>(0.0)) || any(history_uv > vec2<f32>(1.0))) {
+        return current;
+    }
+
+    let mean = moment1 / 9.0;
+    let deviation = sqrt(max(moment2 / 9.0 - mean * mean, vec3<f32>(0.0)));
+    let reprojected = textureSampleLevel(history, history_sampler, history_uv, 0.0).rgb;
+    let clipped = clip_to_box(weight(rgb_to_ycocg(max(reprojected, vec3<f32>(0.0)))), mean, deviation * CLIP_GAMMA);
+
+    let color = weight(rgb_to_ycocg(max(current.rgb, vec3<f32>(0.0))));
+    let blended = mix(color, clipped, params.history_weight);
+    return vec4<f32>(ycocg_to_rgb(unweight(blended)), current.a);
+}
+
+// Unsharp mask against the four direct neighbours. Reads the resolved
+// history through `scene` and writes the scene texture, leaving the history
+// itself unsharpened.
+@fragment
+fn fs_sharpen(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(scene));
+    let coord = vec2<i32>(position.xy);
+    let center = textureLoad(scene, coord, 0);
+    if (params.sharpness <= 0.0) {
+        return center;
+    }
+
+    let neighbours = textureLoad(scene, clamp(coord + vec2<i32>(1, 0), vec2<i32>(0), size - 1), 0).rgb
+        + textureLoad(scene, clamp(coord - vec2<i32>(1, 0), vec2<i32>(0), size - 1), 0).rgb
+        + textureLoad(scene, clamp(coord + vec2<i32>(0, 1), vec2<i32>(0), size - 1), 0).rgb
+        + textureLoad(scene, clamp(coord - vec2<i32>(0, 1), vec2<i32>(0), size - 1), 0).rgb;
+    let sharpened = center.rgb + (center.rgb - neighbours * 0.25) * params.sharpness;
+    return vec4<f32>(max(sharpened, vec3<f32>(0.0)), center.a);
+}
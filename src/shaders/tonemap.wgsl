@@ -0,0 +1,129 @@
+// Resolves the linear HDR scene color to the display: exposure, then one of
+// the tone mapping curves, then sRGB encoding when the target doesn't do it.
+// Concatenated after srgb.wgsl.
+
+struct Params {
+    exposure: f32,
+    curve: u32,
+    encode_srgb: u32,
+    padding: f32,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var hdr: texture_2d<f32>;
+
+// Must match ToneMapping in tonemap.rs
+const ACES: u32 = 0u;
+const REINHARD: u32 = 1u;
+const AGX: u32 = 2u;
+const PBR_NEUTRAL: u32 = 3u;
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Stephen Hill's fit of the ACES RRT + sRGB ODT
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let input = mat3x3<f32>(
+        0.59719, 0.07600, 0.02840,
+        0.35458, 0.90834, 0.13383,
+        0.04823, 0.01566, 0.83777,
+    );
+    let output = mat3x3<f32>(
+        1.60475, -0.10208, -0.00327,
+        -0.53108, 1.10813, -0.07276,
+        -0.07367, -0.00605, 1.07602,
+    );
+    let v = input * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(output * (a / b), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color);
+}
+
+// Minimal AgX with the default look, after Benjamin Wrensch's fit
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    );
+    let outset = mat3x3<f32>(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var x = inset * color;
+    x = clamp(log2(max(x, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    x = (x - min_ev) / (max_ev - min_ev);
+
+    // Sigmoid contrast curve
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    x = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+
+    // The curve outputs display-encoded values; undo that so the sRGB
+    // encoding below applies uniformly
+    x = outset * x;
+    return pow(max(x, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+// Khronos PBR Neutral: leaves base colors untouched up to 0.8 and only
+// compresses highlights
+fn pbr_neutral(color: vec3<f32>) -> vec3<f32> {
+    let start_compression = 0.8 - 0.04;
+    let desaturation = 0.15;
+
+    let x = min(color.r, min(color.g, color.b));
+    var offset = 0.04;
+    if (x < 0.08) {
+        offset = x - 6.25 * x * x;
+    }
+    var c = color - offset;
+
+    let peak = max(c.r, max(c.g, c.b));
+    if (peak < start_compression) {
+        return c;
+    }
+    let d = 1.0 - start_compression;
+    let new_peak = 1.0 - d * d / (peak + d - start_compression);
+    c *= new_peak / peak;
+    let g = 1.0 - 1.0 / (desaturation * (peak - new_peak) + 1.0);
+    return mix(c, vec3<f32>(new_peak), g);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let scene = textureLoad(hdr, vec2<i32>(position.xy), 0);
+    let color = max(scene.rgb * params.exposure, vec3<f32>(0.0));
+
+    var mapped: vec3<f32>;
+    switch params.curve {
+        case ACES: {
+            mapped = aces(color);
+        }
+        case REINHARD: {
+            mapped = reinhard(color);
+        }
+        case AGX: {
+            mapped = agx(color);
+        }
+        case PBR_NEUTRAL, default: {
+            mapped = pbr_neutral(color);
+        }
+    }
+    mapped = clamp(mapped, vec3<f32>(0.0), vec3<f32>(1.0));
+
+    if (params.encode_srgb != 0u) {
+        mapped = srgb_encode(mapped);
+    }
+    return vec4<f32>(mapped, scene.a);
+}
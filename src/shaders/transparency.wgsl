@@ -0,0 +1,51 @@
+// Weighted blended order-independent transparency, after McGuire and
+// Bavoil, "Weighted Blended Order-Independent Transparency". Translucent
+// spheres are drawn in any order against the opaque depth without writing
+// it: each fragment adds its premultiplied color, weighted by distance, to
+// the accumulation target and multiplies the revealage target by
+// 1 - alpha. transparency_composite.wgsl then blends the weighted average
+// over the scene. Concatenated after impostor.wgsl.
+
+struct AccumulationOutput {
+    @location(0) accumulation: vec4<f32>,
+    @location(1) revealage: vec4<f32>,
+};
+
+struct TransparentImpostorFragment {
+    @location(0) accumulation: vec4<f32>,
+    @location(1) revealage: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+// Equation 7 of the paper with distance in world units. The upper bound is
+// lower than the paper's so sums of bright HDR layers stay finite in half
+// floats.
+fn accumulate(color: vec4<f32>, world_pos: vec3<f32>) -> AccumulationOutput {
+    let alpha = clamp(color.a, 0.0, 1.0);
+    let distance = length(world_pos - camera.position.xyz);
+    let weight = alpha * clamp(10.0 / (1e-5 + pow(distance / 5.0, 2.0) + pow(distance / 200.0, 6.0)), 1e-2, 300.0);
+
+    var out: AccumulationOutput;
+    out.accumulation = vec4<f32>(color.rgb * alpha, alpha) * weight;
+    out.revealage = vec4<f32>(alpha);
+    return out;
+}
+
+@fragment
+fn fs_transparent(in: VertexOutput) -> AccumulationOutput {
+    let color = tint_selected(shade(in.world_pos, in.normal, in.material_index, in.occlusion), in.sphere_index);
+    return accumulate(color, in.world_pos);
+}
+
+@fragment
+fn fs_impostor_transparent(in: ImpostorOutput) -> TransparentImpostorFragment {
+    let hit = ray_cast_impostor(in, false);
+    let color = tint_selected(shade(hit.position, hit.normal, in.material_index, in.occlusion), in.sphere_index);
+    let accumulated = accumulate(color, hit.position);
+
+    var out: TransparentImpostorFragment;
+    out.accumulation = accumulated.accumulation;
+    out.revealage = accumulated.revealage;
+    out.depth = hit.depth;
+    return out;
+}
@@ -0,0 +1,24 @@
+// Blends the weighted average of the translucent layers over the scene,
+// with the coverage the revealage target left behind. See transparency.wgsl.
+
+@group(0) @binding(0) var accumulation: texture_2d<f32>;
+@group(0) @binding(1) var revealage: texture_2d<f32>;
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_composite(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(position.xy);
+    let revealed = textureLoad(revealage, coord, 0).r;
+    if (revealed >= 1.0) {
+        // No translucent surface covers this pixel
+        discard;
+    }
+    let sum = textureLoad(accumulation, coord, 0);
+    let average = sum.rgb / clamp(sum.a, 1e-5, 5e4);
+    return vec4<f32>(average, 1.0 - revealed);
+}
@@ -0,0 +1,63 @@
+// Screen-space motion of the visible sphere surfaces, for temporal
+// antialiasing. Concatenated after impostor.wgsl, whose bindings and ray
+// cast it reuses. Drawn against the finished depth buffer without writing
+// it, so only the front-most surface of each pixel lands in the target.
+
+// Sphere positions as of the previous frame
+@group(1) @binding(5) var<storage, read> previous_spheres: array<Sphere>;
+
+struct VelocityOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) clip: vec4<f32>,
+    @location(1) previous_clip: vec4<f32>,
+};
+
+// Offset in uv from where a point was on screen last frame to where it is
+// now, with this frame's jitter taken out
+fn motion(clip: vec4<f32>, previous_clip: vec4<f32>) -> vec2<f32> {
+    let ndc = clip.xy / clip.w - camera.jitter.xy;
+    let previous_ndc = previous_clip.xy / previous_clip.w;
+    return (ndc - previous_ndc) * vec2<f32>(0.5, -0.5);
+}
+
+@vertex
+fn vs_velocity(
+    @location(0) position: vec3<f32>,
+    @builtin(instance_index) instance_index: u32,
+) -> VelocityOutput {
+    let sphere_index = visible_spheres[instance_index];
+    let sphere = spheres[sphere_index];
+    let previous = previous_spheres[sphere_index];
+
+    // Same expression as vs_main so the depth test passes on equal depth
+    let world_position = sphere.position + position * sphere.radius;
+    var out: VelocityOutput;
+    out.position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.clip = out.position;
+    out.previous_clip = camera.previous_view_proj * vec4<f32>(previous.position + position * previous.radius, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_velocity(in: VelocityOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(motion(in.clip, in.previous_clip), 0.0, 0.0);
+}
+
+struct ImpostorVelocity {
+    @location(0) velocity: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+@fragment
+fn fs_impostor_velocity(in: ImpostorOutput) -> ImpostorVelocity {
+    let hit = ray_cast_impostor(in, false);
+    let previous = previous_spheres[in.sphere_index];
+    let previous_hit = previous.position + (hit.position - in.center) * (previous.radius / in.radius);
+
+    let clip = camera.view_proj * vec4<f32>(hit.position, 1.0);
+    let previous_clip = camera.previous_view_proj * vec4<f32>(previous_hit, 1.0);
+    var out: ImpostorVelocity;
+    out.velocity = vec4<f32>(motion(clip, previous_clip), 0.0, 0.0);
+    out.depth = hit.depth;
+    return out;
+}
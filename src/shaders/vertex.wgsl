@@ -1,38 +1,54 @@
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-};
-
-struct InstanceInput {
-    @location(1) position: vec3<f32>,
-    @location(2) radius: f32,
-    @location(3) material_index: u32,
+struct Sphere {
+    position: vec3<f32>,
+    radius: f32,
+    material_index: u32,
+    padding: array<u32, 3>,
 };
 
 struct Camera {
+    // Jittered while temporal antialiasing is on
     view_proj: mat4x4<f32>,
+    position: vec4<f32>,
+    // Last frame's unjittered view_proj, for the velocity pass
+    previous_view_proj: mat4x4<f32>,
+    // This frame's jitter as an NDC offset in xy
+    jitter: vec4<f32>,
+    // Near and far depth of the light cluster slices in xy
+    clip_planes: vec4<f32>,
 };
 
 @group(0) @binding(0) var<uniform> camera: Camera;
+@group(1) @binding(0) var<storage, read> spheres: array<Sphere>;
+// Indices of the spheres that survived culling, in draw order
+@group(1) @binding(2) var<storage, read> visible_spheres: array<u32>;
+// Ambient occlusion from neighbouring spheres, see ambient_occlusion.wgsl
+@group(1) @binding(4) var<storage, read> sphere_occlusion: array<vec4<f32>>;
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) world_pos: vec3<f32>,
     @location(1) normal: vec3<f32>,
-    @location(2) material: vec3<f32>,
+    @location(2) @interpolate(flat) material_index: u32,
+    @location(3) @interpolate(flat) occlusion: vec4<f32>,
+    @location(4) @interpolate(flat) sphere_index: u32,
 };
 
 @vertex
 fn vs_main(
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
-    @location(2) instance_position: vec3<f32>,
-    @location(3) instance_material: vec3<f32>,
+    @builtin(instance_index) instance_index: u32,
 ) -> VertexOutput {
+    let sphere_index = visible_spheres[instance_index];
+    let sphere = spheres[sphere_index];
+
     var out: VertexOutput;
-    let world_position = position + instance_position;
+    let world_position = sphere.position + position * sphere.radius;
     out.position = camera.view_proj * vec4<f32>(world_position, 1.0);
     out.world_pos = world_position;
     out.normal = normal;
-    out.material = instance_material;
+    out.material_index = sphere.material_index;
+    out.occlusion = sphere_occlusion[sphere_index];
+    out.sphere_index = sphere_index;
     return out;
-}
\ No newline at end of file
+}
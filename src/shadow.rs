@@ -0,0 +1,736 @@
+use glam::{Mat3, Mat4, Vec3};
+
+use crate::mesh::Vertex;
+use crate::renderer::clip_planes;
+
+/// Upper bound for [`ShadowSettings::cascade_count`].
+pub const MAX_CASCADES: usize = 4;
+
+/// How many point lights can cast shadows at once. Further shadow-casting
+/// point lights are lit without shadows.
+pub const MAX_POINT_SHADOWS: usize = 4;
+
+/// Upper bound on the shadow views rendered per frame.
+pub const MAX_SHADOW_VIEWS: usize = MAX_CASCADES + 6 * MAX_POINT_SHADOWS;
+
+// Near plane of the cube face projections
+const POINT_SHADOW_NEAR: f32 = 0.05;
+
+const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// Shadow maps: cascades for the first directional light and a cube map for
+/// every point light with shadows turned on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowSettings {
+    pub enabled: bool,
+    /// Number of cascades the camera frustum is split into, 1 to [`MAX_CASCADES`].
+    pub cascade_count: u32,
+    /// Width and height of each cascade's depth map.
+    pub resolution: u32,
+    /// Width and height of each face of a point light's cube map.
+    pub cube_resolution: u32,
+    /// Subtracted from the receiver's light-space depth, in 0..1 depth units.
+    /// Cube maps store distance over the light's range as depth.
+    pub depth_bias: f32,
+    /// Receiver offset along its normal, in shadow map texels.
+    pub normal_bias: f32,
+    /// Shadows end this far from the camera. Casters up to this far beyond a
+    /// cascade, towards the light, are still included. Also the shadow range
+    /// of point lights without a range of their own.
+    pub max_distance: f32,
+    /// Blend between uniform (0) and logarithmic (1) cascade splits.
+    pub split_lambda: f32,
+    /// Tint every pixel by the cascade it samples, for tuning the splits.
+    pub show_cascades: bool,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            cascade_count: 4,
+            resolution: 2048,
+            cube_resolution: 512,
+            depth_bias: 0.0002,
+            normal_bias: 1.5,
+            max_distance: 150.0,
+            split_lambda: 0.75,
+            show_cascades: false,
+        }
+    }
+}
+
+/// A shadow-casting point light, see [`ShadowMaps::update`].
+pub(crate) struct PointShadow {
+    pub position: Vec3,
+    pub range: Option<f32>,
+}
+
+/// One depth render of the shadow casters, culled like a camera view.
+pub(crate) struct ShadowView {
+    pub view_proj: Mat4,
+    /// Projected radius in texels of a unit sphere at view depth 1, for LOD
+    /// selection.
+    pub lod_scale: f32,
+    target: ShadowTarget,
+}
+
+#[derive(Clone, Copy)]
+enum ShadowTarget {
+    Cascade(usize),
+    /// Layer of the cube map array: cube index * 6 + face.
+    CubeFace(usize),
+}
+
+/// Mirrors `ShadowView` in shadow.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowViewUniform {
+    view_proj: [[f32; 4]; 4],
+    origin: [f32; 4],
+}
+
+/// Mirrors `Shadows` in fragment.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    cascade_view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+    splits: [f32; MAX_CASCADES],
+    texel_sizes: [f32; MAX_CASCADES],
+    camera_forward: [f32; 4],
+    cascade_count: u32,
+    depth_bias: f32,
+    normal_bias: f32,
+    show_cascades: u32,
+    point_inv_ranges: [f32; MAX_POINT_SHADOWS],
+    cube_texel_scale: f32,
+    _padding: [f32; 3],
+}
+
+struct Cascade {
+    view_proj: Mat4,
+    /// View depth where the cascade ends.
+    split: f32,
+    /// World-space size of one shadow map texel.
+    texel_size: f32,
+}
+
+/// Splits the camera frustum into cascades and fits an orthographic light
+/// projection around each slice.
+fn fit_cascades(settings: &ShadowSettings, view: Mat4, projection: Mat4, light_direction: Vec3) -> Vec<Cascade> {
+    // The far plane may be infinite; the cascades end at max_distance anyway
+    let (near, far) = clip_planes(projection);
+    let shadow_far = far.min(settings.max_distance);
+    let count = settings.cascade_count.clamp(1, MAX_CASCADES as u32);
+
+    // Frustum edges as rays from the eye through the near plane corners,
+    // which reach view depth d at d / near of the way
+    let eye = view.inverse().w_axis.truncate();
+    let inverse = (projection * view).inverse();
+    let frustum_edges = [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+        .map(|(x, y)| inverse.project_point3(Vec3::new(x, y, 0.0)) - eye);
+
+    let light_direction = light_direction.normalize();
+    let up = if light_direction.y.abs() > 0.99 { Vec3::X } else { Vec3::Y };
+    let light_view = Mat4::look_at_rh(Vec3::ZERO, light_direction, up);
+
+    let mut slice_near = near;
+    (1..=count)
+        .map(|index| {
+            let t = index as f32 / count as f32;
+            let logarithmic = near * (shadow_far / near).powf(t);
+            let uniform = near + (shadow_far - near) * t;
+            let split = settings.split_lambda * logarithmic + (1.0 - settings.split_lambda) * uniform;
+
+            let corners: Vec<Vec3> = frustum_edges
+                .iter()
+                .flat_map(|edge| [slice_near, split].map(|depth| eye + *edge * (depth / near)))
+                .collect();
+            slice_near = split;
+
+            // A bounding sphere keeps the cascade size fixed as the camera turns
+            let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+            let radius = corners.iter().map(|corner| corner.distance(center)).fold(0.0, f32::max);
+            let radius = (radius * 16.0).ceil() / 16.0;
+            let texel_size = 2.0 * radius / settings.resolution as f32;
+
+            // Move in whole texels only, so edges don't shimmer as the camera moves
+            let mut center = light_view.transform_point3(center);
+            center.x = (center.x / texel_size).floor() * texel_size;
+            center.y = (center.y / texel_size).floor() * texel_size;
+
+            let projection = Mat4::orthographic_rh(
+                center.x - radius,
+                center.x + radius,
+                center.y - radius,
+                center.y + radius,
+                -center.z - radius - settings.max_distance,
+                -center.z + radius,
+            );
+
+            Cascade {
+                view_proj: projection * light_view,
+                split,
+                texel_size,
+            }
+        })
+        .collect()
+}
+
+/// View of each cube face, matching the face order and texel orientation
+/// that cube map sampling expects. These are mirror images, so triangles
+/// come out with clockwise winding.
+fn cube_face_views(position: Vec3) -> [Mat4; 6] {
+    // Forward, screen right and screen up for +X, -X, +Y, -Y, +Z, -Z
+    let faces = [
+        (Vec3::X, Vec3::NEG_Z, Vec3::Y),
+        (Vec3::NEG_X, Vec3::Z, Vec3::Y),
+        (Vec3::Y, Vec3::X, Vec3::NEG_Z),
+        (Vec3::NEG_Y, Vec3::X, Vec3::Z),
+        (Vec3::Z, Vec3::X, Vec3::Y),
+        (Vec3::NEG_Z, Vec3::NEG_X, Vec3::Y),
+    ];
+    faces.map(|(forward, right, up)| {
+        Mat4::from_mat3(Mat3::from_cols(right, up, -forward).transpose()) * Mat4::from_translation(-position)
+    })
+}
+
+/// Depth maps and caster pipelines for cascaded and point light shadows.
+/// The caster passes draw the buckets produced by running the culling
+/// shader for each [`ShadowView`]; the lighting shaders read the maps
+/// through [`Self::bind_group`].
+pub(crate) struct ShadowMaps {
+    settings: ShadowSettings,
+    view_buffer: wgpu::Buffer,
+    view_bind_group: wgpu::BindGroup,
+    view_stride: u64,
+    mesh_pipeline: wgpu::RenderPipeline,
+    impostor_pipeline: wgpu::RenderPipeline,
+    cube_mesh_pipeline: wgpu::RenderPipeline,
+    cube_impostor_pipeline: wgpu::RenderPipeline,
+    cascade_layers: Vec<wgpu::TextureView>,
+    cube_faces: Vec<wgpu::TextureView>,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMaps {
+    pub fn new(device: &wgpu::Device, sphere_layout: &wgpu::BindGroupLayout, settings: ShadowSettings) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shadow.wgsl").into()),
+        });
+
+        // One view uniform per shadow view, selected with a dynamic offset
+        let view_stride = (std::mem::size_of::<ShadowViewUniform>() as u64)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64);
+        let view_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow View Buffer"),
+            size: MAX_SHADOW_VIEWS as u64 * view_stride,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let view_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow View Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let view_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow View Bind Group"),
+            layout: &view_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &view_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<ShadowViewUniform>() as u64),
+                    }),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&view_layout, sphere_layout],
+            push_constant_ranges: &[],
+        });
+
+        let depth_stencil = wgpu::DepthStencilState {
+            format: SHADOW_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        };
+
+        let mesh_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Mesh Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_shadow_mesh"),
+                buffers: &[Vertex::layout()],
+                compilation_options: Default::default(),
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(depth_stencil.clone()),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let impostor_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Impostor Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_shadow_impostor"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_shadow_impostor"),
+                targets: &[],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(depth_stencil.clone()),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        // Cube faces store the distance to the light rather than the
+        // projected depth, so the casters always write frag_depth
+        let cube_mesh_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Cube Shadow Mesh Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_cube_shadow_mesh"),
+                buffers: &[Vertex::layout()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_cube_shadow_mesh"),
+                targets: &[],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Cw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(depth_stencil.clone()),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let cube_impostor_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Cube Shadow Impostor Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_shadow_impostor"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_cube_shadow_impostor"),
+                targets: &[],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(depth_stencil),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            size: std::mem::size_of::<ShadowUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Linear filtering turns every comparison into a 2x2 PCF tap
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::CubeArray,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let (cascade_layers, cube_faces, bind_group) =
+            Self::create_targets(device, &bind_group_layout, &uniform_buffer, &sampler, &settings);
+
+        Self {
+            settings,
+            view_buffer,
+            view_bind_group,
+            view_stride,
+            mesh_pipeline,
+            impostor_pipeline,
+            cube_mesh_pipeline,
+            cube_impostor_pipeline,
+            cascade_layers,
+            cube_faces,
+            uniform_buffer,
+            sampler,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    pub fn settings(&self) -> ShadowSettings {
+        self.settings
+    }
+
+    /// Reallocates the depth maps when the cascade count or a resolution
+    /// change.
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings) {
+        let settings = ShadowSettings {
+            cascade_count: settings.cascade_count.clamp(1, MAX_CASCADES as u32),
+            resolution: settings.resolution.clamp(1, device.limits().max_texture_dimension_2d),
+            cube_resolution: settings.cube_resolution.clamp(1, device.limits().max_texture_dimension_2d),
+            ..settings
+        };
+        if settings.cascade_count != self.settings.cascade_count
+            || settings.resolution != self.settings.resolution
+            || settings.cube_resolution != self.settings.cube_resolution
+        {
+            (self.cascade_layers, self.cube_faces, self.bind_group) =
+                Self::create_targets(device, &self.bind_group_layout, &self.uniform_buffer, &self.sampler, &settings);
+        }
+        self.settings = settings;
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn mesh_pipeline(&self, view: &ShadowView) -> &wgpu::RenderPipeline {
+        match view.target {
+            ShadowTarget::Cascade(_) => &self.mesh_pipeline,
+            ShadowTarget::CubeFace(_) => &self.cube_mesh_pipeline,
+        }
+    }
+
+    pub fn impostor_pipeline(&self, view: &ShadowView) -> &wgpu::RenderPipeline {
+        match view.target {
+            ShadowTarget::Cascade(_) => &self.impostor_pipeline,
+            ShadowTarget::CubeFace(_) => &self.cube_impostor_pipeline,
+        }
+    }
+
+    /// Fits the cascades to the camera, places the cube maps of up to
+    /// [`MAX_POINT_SHADOWS`] point lights and uploads them. Returns every
+    /// view to render, cascades first, then six faces per point light in
+    /// the order given.
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        view: Mat4,
+        projection: Mat4,
+        light_direction: Option<Vec3>,
+        point_lights: &[PointShadow],
+    ) -> Vec<ShadowView> {
+        if !self.settings.enabled {
+            return Vec::new();
+        }
+        let cascades = match light_direction {
+            Some(direction) => fit_cascades(&self.settings, view, projection, direction),
+            None => Vec::new(),
+        };
+        let point_lights = &point_lights[..point_lights.len().min(MAX_POINT_SHADOWS)];
+
+        let mut uniform = ShadowUniform {
+            cascade_view_proj: [Mat4::IDENTITY.to_cols_array_2d(); MAX_CASCADES],
+            splits: [0.0; MAX_CASCADES],
+            texel_sizes: [0.0; MAX_CASCADES],
+            camera_forward: (-view.row(2).truncate()).extend(0.0).to_array(),
+            cascade_count: cascades.len() as u32,
+            depth_bias: self.settings.depth_bias,
+            normal_bias: self.settings.normal_bias,
+            show_cascades: self.settings.show_cascades as u32,
+            point_inv_ranges: [0.0; MAX_POINT_SHADOWS],
+            // A face spans 90 degrees, so a texel covers 2 / resolution
+            // world units per unit of distance from the light
+            cube_texel_scale: 2.0 / self.settings.cube_resolution as f32,
+            _padding: [0.0; 3],
+        };
+
+        let mut views = Vec::new();
+        for (index, cascade) in cascades.iter().enumerate() {
+            uniform.cascade_view_proj[index] = cascade.view_proj.to_cols_array_2d();
+            uniform.splits[index] = cascade.split;
+            uniform.texel_sizes[index] = cascade.texel_size;
+
+            let view_uniform = ShadowViewUniform {
+                view_proj: cascade.view_proj.to_cols_array_2d(),
+                origin: light_direction.unwrap_or(Vec3::NEG_Y).normalize().extend(0.0).to_array(),
+            };
+            self.write_view(queue, views.len(), &view_uniform);
+            views.push(ShadowView {
+                view_proj: cascade.view_proj,
+                lod_scale: cascade.view_proj.y_axis.y * 0.5 * self.settings.resolution as f32,
+                target: ShadowTarget::Cascade(index),
+            });
+        }
+
+        for (index, light) in point_lights.iter().enumerate() {
+            let range = light.range.unwrap_or(self.settings.max_distance);
+            uniform.point_inv_ranges[index] = 1.0 / range;
+
+            let projection = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, POINT_SHADOW_NEAR, range);
+            for (face, face_view) in cube_face_views(light.position).into_iter().enumerate() {
+                let view_proj = projection * face_view;
+                let view_uniform = ShadowViewUniform {
+                    view_proj: view_proj.to_cols_array_2d(),
+                    origin: light.position.extend(1.0 / range).to_array(),
+                };
+                self.write_view(queue, views.len(), &view_uniform);
+                views.push(ShadowView {
+                    view_proj,
+                    lod_scale: projection.y_axis.y * 0.5 * self.settings.cube_resolution as f32,
+                    target: ShadowTarget::CubeFace(index * 6 + face),
+                });
+            }
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        views
+    }
+
+    fn write_view(&self, queue: &wgpu::Queue, slot: usize, view_uniform: &ShadowViewUniform) {
+        queue.write_buffer(&self.view_buffer, slot as u64 * self.view_stride, bytemuck::bytes_of(view_uniform));
+    }
+
+    /// Clears the target of `views[slot]` from [`Self::update`] and binds the
+    /// view; the caller draws the casters.
+    pub fn begin_pass<'a>(
+        &self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        slot: usize,
+        view: &ShadowView,
+    ) -> wgpu::RenderPass<'a> {
+        let target = match view.target {
+            ShadowTarget::Cascade(cascade) => &self.cascade_layers[cascade],
+            ShadowTarget::CubeFace(layer) => &self.cube_faces[layer],
+        };
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Caster Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: target,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_bind_group(0, &self.view_bind_group, &[(slot as u64 * self.view_stride) as u32]);
+        pass
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        sampler: &wgpu::Sampler,
+        settings: &ShadowSettings,
+    ) -> (Vec<wgpu::TextureView>, Vec<wgpu::TextureView>, wgpu::BindGroup) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Cascade Texture"),
+            size: wgpu::Extent3d {
+                width: settings.resolution,
+                height: settings.resolution,
+                depth_or_array_layers: settings.cascade_count,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let layers = (0..settings.cascade_count)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow Cascade Layer"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let cube_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Cube Texture"),
+            size: wgpu::Extent3d {
+                width: settings.cube_resolution,
+                height: settings.cube_resolution,
+                depth_or_array_layers: 6 * MAX_POINT_SHADOWS as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let cube_faces = (0..6 * MAX_POINT_SHADOWS as u32)
+            .map(|layer| {
+                cube_texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow Cube Face"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let cube_array_view = cube_texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::CubeArray),
+            ..Default::default()
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&array_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&cube_array_view),
+                },
+            ],
+        });
+
+        (layers, cube_faces, bind_group)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cascades_ignore_an_infinite_far_plane() {
+        let settings = ShadowSettings::default();
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 2.0, 10.0), Vec3::ZERO, Vec3::Y);
+        let light_direction = Vec3::new(-1.0, -2.0, -0.5);
+        let finite = fit_cascades(
+            &settings,
+            view,
+            Mat4::perspective_rh(1.0, 1.5, 0.1, settings.max_distance * 10.0),
+            light_direction,
+        );
+        let infinite = fit_cascades(&settings, view, Mat4::perspective_infinite_rh(1.0, 1.5, 0.1), light_direction);
+
+        assert_eq!(finite.len(), infinite.len());
+        for (finite, infinite) in finite.iter().zip(&infinite) {
+            assert!(infinite.split.is_finite() && infinite.texel_size.is_finite());
+            assert!((finite.split - infinite.split).abs() < 1e-3 * finite.split);
+            assert!((finite.texel_size - infinite.texel_size).abs() < 1e-3 * finite.texel_size);
+        }
+        assert!((infinite.last().unwrap().split - settings.max_distance).abs() < 1e-3 * settings.max_distance);
+    }
+}
@@ -0,0 +1,496 @@
+use glam::Mat4;
+
+use crate::tonemap::HDR_FORMAT;
+
+const OCCLUSION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+/// Screen-space ambient occlusion, reconstructed from the depth buffer at
+/// half resolution. It darkens only the image-based ambient light, which
+/// the sphere passes write to a target of their own while it is enabled;
+/// direct lighting and emission are left alone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    /// World-space radius of the sampled hemisphere.
+    pub radius: f32,
+    /// Exponent applied to the unoccluded fraction; higher is darker.
+    pub intensity: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            radius: 1.0,
+            intensity: 1.5,
+        }
+    }
+}
+
+/// Mirrors `Params` in ssao.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    projection: [[f32; 4]; 4],
+    inverse_projection: [[f32; 4]; 4],
+    radius: f32,
+    intensity: f32,
+    _padding: [f32; 2],
+}
+
+/// Occlusion, blur and composite passes. The occlusion and blur passes
+/// ping-pong between two half resolution textures, and the composite pass
+/// subtracts the occluded part of the ambient light from the frame.
+pub(crate) struct Ssao {
+    settings: SsaoSettings,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    ambient_layout: wgpu::BindGroupLayout,
+    occlusion_pipeline: wgpu::RenderPipeline,
+    blur_horizontal_pipeline: wgpu::RenderPipeline,
+    blur_vertical_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    targets: SsaoTargets,
+    // Only allocated while enabled
+    ambient: Option<AmbientTarget>,
+    sample_count: u32,
+    width: u32,
+    height: u32,
+}
+
+/// The sphere passes' ambient light at full resolution, multisampled to
+/// match them and resolved into `view` under MSAA.
+struct AmbientTarget {
+    view: wgpu::TextureView,
+    multisampled_view: Option<wgpu::TextureView>,
+    /// Binds `view` for the composite pass.
+    read_ambient: wgpu::BindGroup,
+}
+
+struct SsaoTargets {
+    occlusion_view: wgpu::TextureView,
+    blur_view: wgpu::TextureView,
+    /// Binds the occlusion texture for reading.
+    read_occlusion: wgpu::BindGroup,
+    /// Binds the blur texture for reading.
+    read_blur: wgpu::BindGroup,
+}
+
+impl Ssao {
+    pub fn new(
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("SSAO Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/ssao.wgsl").into()),
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SSAO Params Buffer"),
+            size: std::mem::size_of::<SsaoUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("SSAO Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSAO Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        // Only the composite pass reads the ambient light
+        let ambient_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSAO Ambient Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSAO Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSAO Composite Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, &ambient_layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |label: &str, entry_point: &str, layout: &wgpu::PipelineLayout, target| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_fullscreen"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(target)],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        let occlusion_target = wgpu::ColorTargetState {
+            format: OCCLUSION_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        };
+        let occlusion_pipeline = create_pipeline("SSAO Pipeline", "fs_occlusion", &pipeline_layout, occlusion_target.clone());
+        let blur_horizontal_pipeline = create_pipeline(
+            "SSAO Horizontal Blur Pipeline",
+            "fs_blur_horizontal",
+            &pipeline_layout,
+            occlusion_target.clone(),
+        );
+        let blur_vertical_pipeline =
+            create_pipeline("SSAO Vertical Blur Pipeline", "fs_blur_vertical", &pipeline_layout, occlusion_target);
+
+        // Subtract the occluded ambient light from the frame, leaving alpha alone
+        let composite_pipeline = create_pipeline(
+            "SSAO Composite Pipeline",
+            "fs_composite",
+            &composite_layout,
+            wgpu::ColorTargetState {
+                format: target_format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::ReverseSubtract,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            },
+        );
+
+        let targets =
+            SsaoTargets::new(device, &bind_group_layout, &uniform_buffer, &sampler, depth_view, width, height);
+
+        let settings = SsaoSettings::default();
+        Self {
+            settings,
+            uniform_buffer,
+            sampler,
+            occlusion_pipeline,
+            blur_horizontal_pipeline,
+            blur_vertical_pipeline,
+            composite_pipeline,
+            targets,
+            ambient: settings
+                .enabled
+                .then(|| AmbientTarget::new(device, &ambient_layout, 1, width, height)),
+            bind_group_layout,
+            ambient_layout,
+            sample_count: 1,
+            width,
+            height,
+        }
+    }
+
+    pub fn settings(&self) -> SsaoSettings {
+        self.settings
+    }
+
+    /// Allocates or frees the ambient target when `enabled` changes, after
+    /// which the sphere pipelines need rebuilding to match
+    /// [`Self::ambient_attachment`].
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: SsaoSettings) {
+        self.settings = settings;
+        if settings.enabled != self.ambient.is_some() {
+            self.ambient = settings
+                .enabled
+                .then(|| AmbientTarget::new(device, &self.ambient_layout, self.sample_count, self.width, self.height));
+        }
+    }
+
+    /// Matches the ambient target to the sphere passes' sample count.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.sample_count = sample_count;
+        if self.ambient.is_some() {
+            self.ambient = Some(AmbientTarget::new(device, &self.ambient_layout, sample_count, self.width, self.height));
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, depth_view: &wgpu::TextureView, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.targets = SsaoTargets::new(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            &self.sampler,
+            depth_view,
+            width,
+            height,
+        );
+        if self.ambient.is_some() {
+            self.ambient = Some(AmbientTarget::new(device, &self.ambient_layout, self.sample_count, width, height));
+        }
+    }
+
+    /// The view the sphere passes write their ambient light to and its
+    /// resolve target, while enabled.
+    pub fn ambient_attachment(&self) -> Option<(&wgpu::TextureView, Option<&wgpu::TextureView>)> {
+        self.ambient.as_ref().map(|ambient| match &ambient.multisampled_view {
+            Some(multisampled_view) => (multisampled_view, Some(&ambient.view)),
+            None => (&ambient.view, None),
+        })
+    }
+
+    /// Darkens the ambient light in `target` by the occlusion of what the
+    /// depth buffer holds. Does nothing while disabled.
+    pub fn encode(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        projection: Mat4,
+    ) {
+        let Some(ambient) = &self.ambient else {
+            return;
+        };
+
+        let uniform = SsaoUniform {
+            projection: projection.to_cols_array_2d(),
+            inverse_projection: projection.inverse().to_cols_array_2d(),
+            radius: self.settings.radius,
+            intensity: self.settings.intensity,
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let passes = [
+            ("SSAO Pass", &self.occlusion_pipeline, &self.targets.occlusion_view, &self.targets.read_blur),
+            (
+                "SSAO Horizontal Blur Pass",
+                &self.blur_horizontal_pipeline,
+                &self.targets.blur_view,
+                &self.targets.read_occlusion,
+            ),
+            (
+                "SSAO Vertical Blur Pass",
+                &self.blur_vertical_pipeline,
+                &self.targets.occlusion_view,
+                &self.targets.read_blur,
+            ),
+            ("SSAO Composite Pass", &self.composite_pipeline, target, &self.targets.read_occlusion),
+        ];
+        for (label, pipeline, view, bind_group) in passes {
+            // The composite pass blends onto the frame, the others overwrite
+            let composite = std::ptr::eq(view, target);
+            let load = if composite {
+                wgpu::LoadOp::Load
+            } else {
+                wgpu::LoadOp::Clear(wgpu::Color::WHITE)
+            };
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            if composite {
+                pass.set_bind_group(1, &ambient.read_ambient, &[]);
+            }
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+impl SsaoTargets {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        sampler: &wgpu::Sampler,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let create_view = |label: &str| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: width.div_ceil(2),
+                        height: height.div_ceil(2),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: OCCLUSION_FORMAT,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let occlusion_view = create_view("SSAO Texture");
+        let blur_view = create_view("SSAO Blur Texture");
+
+        let create_bind_group = |source: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("SSAO Bind Group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(depth_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                ],
+            })
+        };
+        let read_occlusion = create_bind_group(&occlusion_view);
+        let read_blur = create_bind_group(&blur_view);
+
+        Self {
+            occlusion_view,
+            blur_view,
+            read_occlusion,
+            read_blur,
+        }
+    }
+}
+
+impl AmbientTarget {
+    fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, sample_count: u32, width: u32, height: u32) -> Self {
+        let create_view = |label: &str, sample_count: u32, usage: wgpu::TextureUsages| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: HDR_FORMAT,
+                    usage,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let view = create_view(
+            "SSAO Ambient Texture",
+            1,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        );
+        let multisampled_view = (sample_count > 1).then(|| {
+            create_view(
+                "SSAO Multisampled Ambient Texture",
+                sample_count,
+                wgpu::TextureUsages::RENDER_ATTACHMENT,
+            )
+        });
+
+        let read_ambient = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("SSAO Ambient Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            }],
+        });
+
+        Self {
+            view,
+            multisampled_view,
+            read_ambient,
+        }
+    }
+}
@@ -0,0 +1,502 @@
+use glam::Vec2;
+
+use crate::mesh::Vertex;
+use crate::tonemap::HDR_FORMAT;
+
+const VELOCITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+/// Length of the jitter sequence before it repeats.
+const JITTER_SAMPLES: u32 = 8;
+
+/// Temporal antialiasing: each frame is rendered with a different sub-pixel
+/// offset and blended with the reprojected result of earlier frames, which
+/// smooths sub-pixel spheres that MSAA alone leaves sparkling.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TaaSettings {
+    pub enabled: bool,
+    /// Weight of the accumulated history in each new frame, below `1.0`.
+    /// Higher values converge to a smoother image but react slower.
+    pub history_weight: f32,
+    /// Strength of the unsharp mask applied after the resolve, countering
+    /// the softening of reprojection. `0.0` turns it off.
+    pub sharpness: f32,
+}
+
+impl Default for TaaSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            history_weight: 0.9,
+            sharpness: 0.25,
+        }
+    }
+}
+
+/// Mirrors `Params` in taa.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaUniform {
+    history_weight: f32,
+    sharpness: f32,
+    _padding: [f32; 2],
+}
+
+/// Point `index` of the Halton sequence in `base`, in 0..1.
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Velocity buffer, the two history textures the resolve ping-pongs between,
+/// and the pipelines that fill them.
+pub(crate) struct Taa {
+    settings: TaaSettings,
+    frame: u32,
+    // Which history texture the next resolve writes
+    current: usize,
+    history_valid: bool,
+    bindings: TaaBindings,
+    mesh_velocity_pipeline: wgpu::RenderPipeline,
+    impostor_velocity_pipeline: wgpu::RenderPipeline,
+    resolve_pipeline: wgpu::RenderPipeline,
+    sharpen_pipeline: wgpu::RenderPipeline,
+    targets: TaaTargets,
+}
+
+/// What every target bind group shares, kept to rebuild them on resize.
+struct TaaBindings {
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    resolve_layout: wgpu::BindGroupLayout,
+    sharpen_layout: wgpu::BindGroupLayout,
+}
+
+struct TaaTargets {
+    velocity_view: wgpu::TextureView,
+    history_views: [wgpu::TextureView; 2],
+    /// Resolves into history `i`, reading the scene and history `1 - i`.
+    resolve_bind_groups: [wgpu::BindGroup; 2],
+    /// Reads history `i` for the sharpening pass.
+    sharpen_bind_groups: [wgpu::BindGroup; 2],
+}
+
+impl Taa {
+    /// `sphere_layout` is the pipeline layout of the sphere passes, whose
+    /// bind groups the velocity pass reuses.
+    pub fn new(
+        device: &wgpu::Device,
+        sphere_layout: &wgpu::PipelineLayout,
+        depth_format: wgpu::TextureFormat,
+        hdr_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let velocity_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Velocity Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/brdf.wgsl"),
+                    "\n",
+                    include_str!("shaders/fragment.wgsl"),
+                    "\n",
+                    include_str!("shaders/impostor.wgsl"),
+                    "\n",
+                    include_str!("shaders/velocity.wgsl"),
+                )
+                .into(),
+            ),
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("TAA Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/taa.wgsl").into()),
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("TAA Params Buffer"),
+            size: std::mem::size_of::<TaaUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("TAA History Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let texture_entry = |binding: u32, filterable: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let resolve_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TAA Resolve Bind Group Layout"),
+            entries: &[
+                uniform_entry,
+                // scene
+                texture_entry(1, true),
+                // history
+                texture_entry(2, true),
+                // velocity
+                texture_entry(3, true),
+                // depth
+                texture_entry(4, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let sharpen_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TAA Sharpen Bind Group Layout"),
+            entries: &[uniform_entry, texture_entry(1, true)],
+        });
+
+        let depth_stencil = wgpu::DepthStencilState {
+            format: depth_format,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        };
+        let velocity_target = wgpu::ColorTargetState {
+            format: VELOCITY_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL,
+        };
+        let mesh_velocity_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Mesh Velocity Pipeline"),
+            layout: Some(sphere_layout),
+            vertex: wgpu::VertexState {
+                module: &velocity_shader,
+                entry_point: Some("vs_velocity"),
+                buffers: &[Vertex::layout()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &velocity_shader,
+                entry_point: Some("fs_velocity"),
+                targets: &[Some(velocity_target.clone())],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(depth_stencil.clone()),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let impostor_velocity_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Impostor Velocity Pipeline"),
+            layout: Some(sphere_layout),
+            vertex: wgpu::VertexState {
+                module: &velocity_shader,
+                entry_point: Some("vs_impostor"),
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &velocity_shader,
+                entry_point: Some("fs_impostor_velocity"),
+                targets: &[Some(velocity_target)],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(depth_stencil),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let create_pipeline = |label: &str, layout: &wgpu::BindGroupLayout, entry_point: &str| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_fullscreen"),
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: HDR_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let resolve_pipeline = create_pipeline("TAA Resolve Pipeline", &resolve_layout, "fs_resolve");
+        let sharpen_pipeline = create_pipeline("TAA Sharpen Pipeline", &sharpen_layout, "fs_sharpen");
+
+        let bindings = TaaBindings {
+            uniform_buffer,
+            sampler,
+            resolve_layout,
+            sharpen_layout,
+        };
+        let targets = TaaTargets::new(device, &bindings, hdr_view, depth_view, width, height);
+
+        Self {
+            settings: TaaSettings::default(),
+            frame: 0,
+            current: 0,
+            history_valid: false,
+            bindings,
+            mesh_velocity_pipeline,
+            impostor_velocity_pipeline,
+            resolve_pipeline,
+            sharpen_pipeline,
+            targets,
+        }
+    }
+
+    pub fn settings(&self) -> TaaSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: TaaSettings) {
+        if settings.enabled && !self.settings.enabled {
+            self.history_valid = false;
+        }
+        self.settings = settings;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.settings.enabled
+    }
+
+    /// Drops the history, for when the next frame doesn't follow on from
+    /// the last resolved one.
+    pub fn reset_history(&mut self) {
+        self.history_valid = false;
+    }
+
+    /// Sub-pixel offset for the next frame as an NDC translation, from the
+    /// Halton (2, 3) sequence. Zero while disabled.
+    pub fn jitter(&self, width: u32, height: u32) -> Vec2 {
+        if !self.settings.enabled {
+            return Vec2::ZERO;
+        }
+        let index = self.frame % JITTER_SAMPLES + 1;
+        let pixels = Vec2::new(halton(index, 2), halton(index, 3)) - 0.5;
+        pixels * 2.0 / Vec2::new(width as f32, height as f32)
+    }
+
+    pub fn velocity_view(&self) -> &wgpu::TextureView {
+        &self.targets.velocity_view
+    }
+
+    pub fn mesh_velocity_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.mesh_velocity_pipeline
+    }
+
+    pub fn impostor_velocity_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.impostor_velocity_pipeline
+    }
+
+    /// Rebuilds the targets for a new size and drops the history.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.targets = TaaTargets::new(device, &self.bindings, hdr_view, depth_view, width, height);
+        self.history_valid = false;
+    }
+
+    /// Blends `hdr_view` with the history, then writes the sharpened result
+    /// back into it. The velocity pass must have run this frame. Does
+    /// nothing while disabled.
+    pub fn encode(&mut self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, hdr_view: &wgpu::TextureView) {
+        if !self.settings.enabled {
+            return;
+        }
+
+        let uniform = TaaUniform {
+            history_weight: if self.history_valid { self.settings.history_weight.clamp(0.0, 0.99) } else { 0.0 },
+            sharpness: self.settings.sharpness.max(0.0),
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.bindings.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let current = self.current;
+        let passes = [
+            (
+                "TAA Resolve Pass",
+                &self.resolve_pipeline,
+                &self.targets.history_views[current],
+                &self.targets.resolve_bind_groups[current],
+            ),
+            ("TAA Sharpen Pass", &self.sharpen_pipeline, hdr_view, &self.targets.sharpen_bind_groups[current]),
+        ];
+        for (label, pipeline, view, bind_group) in passes {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+
+        self.current = 1 - current;
+        self.history_valid = true;
+        self.frame = self.frame.wrapping_add(1);
+    }
+}
+
+impl TaaTargets {
+    fn new(
+        device: &wgpu::Device,
+        bindings: &TaaBindings,
+        hdr_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let create_view = |label: &str, format: wgpu::TextureFormat| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let velocity_view = create_view("Velocity Texture", VELOCITY_FORMAT);
+        let history_views = [
+            create_view("TAA History Texture", HDR_FORMAT),
+            create_view("TAA History Texture", HDR_FORMAT),
+        ];
+
+        let create_resolve_bind_group = |history: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("TAA Resolve Bind Group"),
+                layout: &bindings.resolve_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: bindings.uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(hdr_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(history),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&velocity_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(depth_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: wgpu::BindingResource::Sampler(&bindings.sampler),
+                    },
+                ],
+            })
+        };
+        let create_sharpen_bind_group = |source: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("TAA Sharpen Bind Group"),
+                layout: &bindings.sharpen_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: bindings.uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                ],
+            })
+        };
+        let resolve_bind_groups = [
+            create_resolve_bind_group(&history_views[1]),
+            create_resolve_bind_group(&history_views[0]),
+        ];
+        let sharpen_bind_groups = [
+            create_sharpen_bind_group(&history_views[0]),
+            create_sharpen_bind_group(&history_views[1]),
+        ];
+
+        Self {
+            velocity_view,
+            history_views,
+            resolve_bind_groups,
+            sharpen_bind_groups,
+        }
+    }
+}
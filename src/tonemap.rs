@@ -0,0 +1,173 @@
+use crate::post::{begin_fullscreen_pass, FullscreenPipelines, PostContext, PostEffect, PostTarget};
+
+/// Format the scene is shaded into before tone mapping.
+pub(crate) const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Curve that maps the HDR scene color into the displayable range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ToneMapping {
+    /// Stephen Hill's fit of the ACES filmic curve. Punchy, with strong
+    /// contrast and hue shifts in bright saturated colors.
+    Aces,
+    /// `c / (1 + c)` per channel. Never clips but flattens highlights.
+    Reinhard,
+    /// AgX, which desaturates towards white instead of skewing hues.
+    AgX,
+    /// Khronos PBR Neutral. Reproduces base colors exactly up to 0.8 and
+    /// only compresses highlights, so materials look as authored.
+    #[default]
+    PbrNeutral,
+}
+
+impl ToneMapping {
+    /// Matches the constants in tonemap.wgsl.
+    fn shader_index(self) -> u32 {
+        match self {
+            ToneMapping::Aces => 0,
+            ToneMapping::Reinhard => 1,
+            ToneMapping::AgX => 2,
+            ToneMapping::PbrNeutral => 3,
+        }
+    }
+}
+
+/// Mirrors `Params` in tonemap.wgsl.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMapUniform {
+    exposure: f32,
+    curve: u32,
+    encode_srgb: u32,
+    _padding: f32,
+}
+
+/// Full-screen pass from the HDR scene color to display values. The last
+/// step of the post chain by default.
+pub(crate) struct ToneMapper {
+    operator: ToneMapping,
+    exposure: f32,
+    uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipelines: FullscreenPipelines,
+}
+
+impl ToneMapper {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tone Mapping Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(include_str!("shaders/srgb.wgsl"), "\n", include_str!("shaders/tonemap.wgsl")).into(),
+            ),
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tone Mapping Params Buffer"),
+            size: std::mem::size_of::<ToneMapUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tone Mapping Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipelines = FullscreenPipelines::new(
+            device,
+            "Tone Mapping Pipeline",
+            shader,
+            &[&bind_group_layout],
+            "fs_main",
+        );
+
+        Self {
+            operator: ToneMapping::default(),
+            exposure: 0.0,
+            uniform_buffer,
+            bind_group_layout,
+            pipelines,
+        }
+    }
+
+    pub fn operator(&self) -> ToneMapping {
+        self.operator
+    }
+
+    pub fn set_operator(&mut self, operator: ToneMapping) {
+        self.operator = operator;
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+    }
+}
+
+impl PostEffect for ToneMapper {
+    fn label(&self) -> &str {
+        "Tone Mapping Pass"
+    }
+
+    fn encode(&mut self, context: &mut PostContext, input: &wgpu::TextureView, output: &PostTarget) {
+        let uniform = ToneMapUniform {
+            exposure: self.exposure.exp2(),
+            curve: self.operator.shader_index(),
+            encode_srgb: output.encode_srgb as u32,
+            _padding: 0.0,
+        };
+        context.queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+
+        let bind_group = create_bind_group(context.device, &self.bind_group_layout, &self.uniform_buffer, input);
+        let pipeline = self.pipelines.get(context.device, output.format);
+        let mut pass = begin_fullscreen_pass(context.encoder, "Tone Mapping Pass", output.view);
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    hdr_view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Tone Mapping Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(hdr_view),
+            },
+        ],
+    })
+}